@@ -0,0 +1,182 @@
+use image::{GenericImageView, Pixel, Rgba};
+
+use crate::oklab::Oklab;
+use crate::{Color, Error, PixelConfig, Quantizer, Region, Sampling, Weighting};
+
+/// Default width of the bands along the edges
+const BORDER_FRACTION: f64 = 0.05;
+/// OKLab distance up to which border colors count as similar to the most common one
+const SIMILAR_DISTANCE: f64 = 0.05;
+
+/// Widths of the bands along the edges of an image, as fractions of its width and height
+///
+/// Bands with a positive width are at least one pixel wide.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Border {
+    /// Band along the top edge, as fraction of the height
+    pub top: f64,
+    /// Band along the right edge, as fraction of the width
+    pub right: f64,
+    /// Band along the bottom edge, as fraction of the height
+    pub bottom: f64,
+    /// Band along the left edge, as fraction of the width
+    pub left: f64,
+}
+
+impl Default for Border {
+    fn default() -> Self {
+        Self::uniform(BORDER_FRACTION)
+    }
+}
+
+impl Border {
+    /// Bands of the same fraction along every edge
+    pub fn uniform(fraction: f64) -> Self {
+        Self {
+            top: fraction,
+            right: fraction,
+            bottom: fraction,
+            left: fraction,
+        }
+    }
+
+    /// Whether a position of an image of the given dimensions is in one of the bands
+    fn in_band(&self, (width, height): (u32, u32)) -> impl Fn(u32, u32) -> bool + Sync {
+        let band =
+            |fraction: f64, side: u32| (fraction.clamp(0_f64, 1_f64) * side as f64).ceil() as u32;
+        let (top, bottom) = (band(self.top, height), band(self.bottom, height));
+        let (left, right) = (band(self.left, width), band(self.right, width));
+        move |x, y| y < top || y + bottom >= height || x < left || x + right >= width
+    }
+}
+
+/// Dominant color along the edges of an image, e.g. to extend the image into
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BorderColor {
+    /// Most common color of the border with the population of its cluster
+    pub color: Color,
+    /// Fraction of the border similar to the color, from 0 to 1
+    ///
+    /// Close to 1 if the border is uniform, low if it has different colors, e.g. when the subject
+    /// touches the edges. Pixels count by their alpha, but clusters of nearly transparent pixels
+    /// still count as at least one pixel.
+    pub confidence: f64,
+}
+
+impl BorderColor {
+    /// Quantize the bands along the edges of an image and find their dominant color
+    ///
+    /// Pixels are weighted by their opacity, so transparent borders fail with [`Error::NoPixels`].
+    pub fn from_image<P, G, Q>(
+        image: &G,
+        border: Border,
+        color_count: usize,
+        sampling: Sampling,
+        quantizer: &Q,
+    ) -> Result<BorderColor, Error>
+    where
+        P: Pixel<Subpixel = u8> + 'static,
+        G: GenericImageView<Pixel = P> + Sync,
+        Q: Quantizer,
+    {
+        let in_band = border.in_band(image.dimensions());
+        let config = PixelConfig {
+            sampling,
+            weighting: Weighting::Alpha,
+            region: Region::default().with_predicate(&in_band),
+        };
+        let colors = quantizer.quantize(image, color_count, &config, |_: &Rgba<u8>| true)?;
+
+        let color = *colors
+            .iter()
+            .max_by_key(|color| color.population)
+            .ok_or(Error::NoPixels)?;
+        let total = colors.iter().map(|c| c.population).sum::<usize>();
+        let dominant = Oklab::from_rgb(&color.color);
+        let similar = colors
+            .iter()
+            .filter(|c| Oklab::from_rgb(&c.color).distance(&dominant) < SIMILAR_DISTANCE)
+            .map(|c| c.population)
+            .sum::<usize>();
+        // Every color has a population of at least one pixel
+        let confidence = similar as f64 / total as f64;
+        Ok(BorderColor { color, confidence })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgb, RgbImage, RgbaImage};
+
+    use super::*;
+    use crate::MedianCut;
+
+    fn border_color<G>(image: &G) -> BorderColor
+    where
+        G: GenericImageView<Pixel = Rgba<u8>> + Sync,
+    {
+        BorderColor::from_image(
+            image,
+            Border::default(),
+            8,
+            Sampling::Full,
+            &MedianCut::default(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn bands_cover_edges() {
+        let border = Border {
+            top: 0.1,
+            right: 0_f64,
+            bottom: 0.25,
+            left: 0.01,
+        };
+        let in_band = border.in_band((20, 8));
+        assert!(in_band(10, 0));
+        assert!(!in_band(10, 1));
+        assert!(in_band(10, 6) && in_band(10, 7));
+        assert!(!in_band(10, 5));
+        assert!(in_band(0, 4));
+        assert!(!in_band(1, 4) && !in_band(19, 4));
+    }
+
+    #[test]
+    fn framed_image_is_uniform() {
+        let image = RgbImage::from_fn(100, 80, |x, y| {
+            if x < 10 || y < 10 || x >= 90 || y >= 70 {
+                Rgb([20, 30, 90])
+            } else {
+                Rgb([(x * 2) as u8, (y * 3) as u8, 200])
+            }
+        });
+        let image = image::DynamicImage::ImageRgb8(image).to_rgba8();
+        let border = border_color(&image);
+        let [r, g, b] = border.color.color.0;
+        assert!(r.abs_diff(20) < 8 && g.abs_diff(30) < 8 && b.abs_diff(90) < 8);
+        assert!(border.confidence > 0.95, "{}", border.confidence);
+    }
+
+    #[test]
+    fn subject_touching_edges_is_not_uniform() {
+        let image = RgbImage::from_fn(100, 80, |x, _| {
+            if x < 50 {
+                Rgb([220, 40, 40])
+            } else {
+                Rgb([40, 40, 220])
+            }
+        });
+        let image = image::DynamicImage::ImageRgb8(image).to_rgba8();
+        let border = border_color(&image);
+        assert!(border.confidence < 0.6, "{}", border.confidence);
+    }
+
+    #[test]
+    fn faint_border_keeps_its_color() {
+        let image = RgbaImage::from_pixel(3, 3, Rgba([200, 100, 50, 1]));
+        let border = border_color(&image);
+        assert_eq!(border.color.population, 1);
+        assert_eq!(border.confidence, 1_f64);
+    }
+}
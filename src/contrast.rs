@@ -0,0 +1,254 @@
+use hsl::HSL;
+use image::{Rgb, Rgba};
+
+use crate::Color;
+
+/// Conformance level of the Web Content Accessibility Guidelines
+#[derive(Debug, Hash, PartialEq, Eq, Clone, Copy)]
+pub enum WcagLevel {
+    /// Minimum contrast, level AA
+    AA,
+    /// Enhanced contrast, level AAA
+    AAA,
+}
+
+/// Size of text as distinguished by the Web Content Accessibility Guidelines
+#[derive(Debug, Hash, PartialEq, Eq, Clone, Copy)]
+pub enum TextSize {
+    /// Text below 18 point, or below 14 point if bold
+    Normal,
+    /// Text of at least 18 point, or 14 point if bold
+    Large,
+}
+
+impl WcagLevel {
+    /// Minimum contrast ratio required for text of the given size
+    pub fn min_contrast(self, size: TextSize) -> f64 {
+        match (self, size) {
+            (WcagLevel::AA, TextSize::Normal) => 4.5,
+            (WcagLevel::AA, TextSize::Large) => 3.0,
+            (WcagLevel::AAA, TextSize::Normal) => 7.0,
+            (WcagLevel::AAA, TextSize::Large) => 4.5,
+        }
+    }
+}
+
+impl Color {
+    /// Relative luminance as defined by WCAG 2, ranging from 0 for black to 1 for white
+    pub fn relative_luminance(&self) -> f64 {
+        relative_luminance(&self.color)
+    }
+
+    /// Contrast ratio between both colors, ranging from 1 to 21
+    pub fn contrast_ratio(&self, other: &Color) -> f64 {
+        contrast_ratio(&self.color, &other.color)
+    }
+
+    /// Whether text in this color on `background` (or vice versa) passes the given level
+    pub fn passes(&self, background: &Color, level: WcagLevel, size: TextSize) -> bool {
+        self.contrast_ratio(background) >= level.min_contrast(size)
+    }
+
+    /// Lighten or darken this color until its contrast to `background` is at least `min_contrast`
+    ///
+    /// Hue and saturation are kept and the lightness changes as little as possible. Returns `None`
+    /// if neither white nor black reach the contrast.
+    pub fn with_min_contrast(&self, background: &Color, min_contrast: f64) -> Option<Color> {
+        adjust_lightness(&self.color, &background.color, min_contrast).map(|color| Color {
+            color,
+            population: self.population,
+        })
+    }
+}
+
+/// Relative luminance of an sRGB color as defined by WCAG 2
+pub(crate) fn relative_luminance(color: &Rgb<u8>) -> f64 {
+    let linear = |c: u8| {
+        let c = c as f64 / 255_f64;
+        if c <= 0.03928 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    let [r, g, b] = color.0;
+    0.2126 * linear(r) + 0.7152 * linear(g) + 0.0722 * linear(b)
+}
+
+/// Contrast ratio between two opaque colors, ranging from 1 to 21
+pub(crate) fn contrast_ratio(a: &Rgb<u8>, b: &Rgb<u8>) -> f64 {
+    let a = relative_luminance(a);
+    let b = relative_luminance(b);
+    (a.max(b) + 0.05) / (a.min(b) + 0.05)
+}
+
+/// Blend a translucent color onto an opaque background
+pub(crate) fn composite(foreground: &Rgba<u8>, background: &Rgb<u8>) -> Rgb<u8> {
+    let alpha = foreground[3] as f64 / 255_f64;
+    let mix = |f: u8, b: u8| (f as f64 * alpha + b as f64 * (1_f64 - alpha)).round() as u8;
+    Rgb([
+        mix(foreground[0], background[0]),
+        mix(foreground[1], background[1]),
+        mix(foreground[2], background[2]),
+    ])
+}
+
+/// Smallest alpha of `foreground` which reaches `min_contrast` on `background`
+///
+/// Returns `None` if even the opaque foreground does not reach it.
+pub(crate) fn min_alpha(
+    foreground: &Rgb<u8>,
+    background: &Rgb<u8>,
+    min_contrast: f64,
+) -> Option<u8> {
+    let with_alpha = |alpha: u8| {
+        let [r, g, b] = foreground.0;
+        composite(&Rgba([r, g, b, alpha]), background)
+    };
+    if contrast_ratio(&with_alpha(u8::MAX), background) < min_contrast {
+        return None;
+    }
+
+    // Binary search for the alpha, `max` always reaches the contrast
+    let (mut min, mut max) = (0_u8, u8::MAX);
+    while max - min > 1 {
+        let alpha = min + (max - min) / 2;
+        if contrast_ratio(&with_alpha(alpha), background) < min_contrast {
+            min = alpha;
+        } else {
+            max = alpha;
+        }
+    }
+    Some(max)
+}
+
+/// Change the HSL lightness of `color` as little as possible to reach `min_contrast`
+pub(crate) fn adjust_lightness(
+    color: &Rgb<u8>,
+    background: &Rgb<u8>,
+    min_contrast: f64,
+) -> Option<Rgb<u8>> {
+    if contrast_ratio(color, background) >= min_contrast {
+        return Some(*color);
+    }
+
+    let hsl = HSL::from_rgb(&color.0);
+    let with_lightness = |l: f64| {
+        let (r, g, b) = HSL { l, ..hsl }.to_rgb();
+        Rgb([r, g, b])
+    };
+    let reaches = |l: f64| contrast_ratio(&with_lightness(l), background) >= min_contrast;
+
+    // Binary search between the current lightness and the extreme in either direction
+    let search = |extreme: f64| {
+        if !reaches(extreme) {
+            return None;
+        }
+        let (mut failing, mut passing) = (hsl.l, extreme);
+        for _ in 0..16 {
+            let l = (failing + passing) / 2_f64;
+            if reaches(l) {
+                passing = l;
+            } else {
+                failing = l;
+            }
+        }
+        Some(passing)
+    };
+
+    let lighter = search(1_f64);
+    let darker = search(0_f64);
+    let l = match (lighter, darker) {
+        (Some(lighter), Some(darker)) => {
+            if lighter - hsl.l <= hsl.l - darker {
+                lighter
+            } else {
+                darker
+            }
+        }
+        (lighter, darker) => lighter.or(darker)?,
+    };
+    Some(with_lightness(l))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::color;
+
+    const WHITE: u32 = 0xFFFFFF;
+    const BLACK: u32 = 0x000000;
+
+    #[test]
+    fn relative_luminance_of_extremes() {
+        assert_eq!(color(BLACK, 1).relative_luminance(), 0_f64);
+        assert!((color(WHITE, 1).relative_luminance() - 1_f64).abs() < 1e-9);
+    }
+
+    #[test]
+    fn contrast_ratio_of_known_colors() {
+        let ratio = |a, b| color(a, 1).contrast_ratio(&color(b, 1));
+        assert!((ratio(WHITE, BLACK) - 21_f64).abs() < 1e-9);
+        assert_eq!(ratio(WHITE, BLACK), ratio(BLACK, WHITE));
+        assert_eq!(ratio(0x336699, 0x336699), 1_f64);
+        assert!((ratio(0x777777, WHITE) - 4.48).abs() < 0.01);
+        assert!((ratio(0x767676, WHITE) - 4.54).abs() < 0.01);
+    }
+
+    #[test]
+    fn levels_have_wcag_thresholds() {
+        assert_eq!(WcagLevel::AA.min_contrast(TextSize::Normal), 4.5);
+        assert_eq!(WcagLevel::AA.min_contrast(TextSize::Large), 3.0);
+        assert_eq!(WcagLevel::AAA.min_contrast(TextSize::Normal), 7.0);
+        assert_eq!(WcagLevel::AAA.min_contrast(TextSize::Large), 4.5);
+
+        let white = color(WHITE, 1);
+        let passes = |hex, level, size| color(hex, 1).passes(&white, level, size);
+        assert!(!passes(0x777777, WcagLevel::AA, TextSize::Normal));
+        assert!(passes(0x777777, WcagLevel::AA, TextSize::Large));
+        assert!(passes(0x767676, WcagLevel::AA, TextSize::Normal));
+        assert!(!passes(0x767676, WcagLevel::AAA, TextSize::Normal));
+        assert!(passes(0x595959, WcagLevel::AAA, TextSize::Normal));
+        assert!(passes(BLACK, WcagLevel::AAA, TextSize::Normal));
+    }
+
+    #[test]
+    fn min_contrast_is_reached() {
+        for &(foreground, background) in &[
+            (0xE05050, WHITE),
+            (0x4080C0, WHITE),
+            (0x204060, BLACK),
+            (0x808080, 0x1A2B3C),
+        ] {
+            for &min_contrast in &[3_f64, 4.5, 7_f64] {
+                let background = color(background, 1);
+                let adjusted = color(foreground, 1)
+                    .with_min_contrast(&background, min_contrast)
+                    .unwrap();
+                let ratio = adjusted.contrast_ratio(&background);
+                assert!(ratio >= min_contrast);
+                // The lightness only changes until the contrast is reached
+                if color(foreground, 1).contrast_ratio(&background) < min_contrast {
+                    assert!(ratio < min_contrast + 0.5, "{}", ratio);
+                }
+            }
+        }
+        // Colors with enough contrast are kept
+        let black = color(BLACK, 1);
+        assert_eq!(
+            color(WHITE, 1).with_min_contrast(&black, 7_f64),
+            Some(color(WHITE, 1))
+        );
+    }
+
+    #[test]
+    fn unreachable_contrast_is_none() {
+        // Mid gray has a contrast of about 3.9 to white and 5.3 to black
+        let gray = color(0x808080, 1);
+        assert_eq!(color(0xE05050, 1).with_min_contrast(&gray, 6_f64), None);
+        assert_eq!(
+            color(WHITE, 1).with_min_contrast(&color(BLACK, 1), 22_f64),
+            None
+        );
+    }
+}
@@ -0,0 +1,297 @@
+use hsl::HSL;
+use image::Rgba;
+
+/// Decides which pixels of an image are quantized
+///
+/// Filters can be combined with [`PixelFilter::and`], [`PixelFilter::or`] and
+/// [`PixelFilter::not`], closures taking a pixel are filters as well.
+pub trait PixelFilter {
+    /// Whether the pixel is quantized
+    fn accepts(&self, pixel: &Rgba<u8>) -> bool;
+
+    /// Accept pixels accepted by both filters
+    fn and<F: PixelFilter>(self, other: F) -> And<Self, F>
+    where
+        Self: Sized,
+    {
+        And(self, other)
+    }
+
+    /// Accept pixels accepted by either filter
+    fn or<F: PixelFilter>(self, other: F) -> Or<Self, F>
+    where
+        Self: Sized,
+    {
+        Or(self, other)
+    }
+
+    /// Accept pixels rejected by this filter
+    fn not(self) -> Not<Self>
+    where
+        Self: Sized,
+    {
+        Not(self)
+    }
+}
+
+impl<F: Fn(&Rgba<u8>) -> bool> PixelFilter for F {
+    fn accepts(&self, pixel: &Rgba<u8>) -> bool {
+        self(pixel)
+    }
+}
+
+/// Accepts pixels accepted by both filters, see [`PixelFilter::and`]
+#[derive(Debug, Clone, Copy)]
+pub struct And<A, B>(A, B);
+
+impl<A: PixelFilter, B: PixelFilter> PixelFilter for And<A, B> {
+    fn accepts(&self, pixel: &Rgba<u8>) -> bool {
+        self.0.accepts(pixel) && self.1.accepts(pixel)
+    }
+}
+
+/// Accepts pixels accepted by either filter, see [`PixelFilter::or`]
+#[derive(Debug, Clone, Copy)]
+pub struct Or<A, B>(A, B);
+
+impl<A: PixelFilter, B: PixelFilter> PixelFilter for Or<A, B> {
+    fn accepts(&self, pixel: &Rgba<u8>) -> bool {
+        self.0.accepts(pixel) || self.1.accepts(pixel)
+    }
+}
+
+/// Accepts pixels rejected by the filter, see [`PixelFilter::not`]
+#[derive(Debug, Clone, Copy)]
+pub struct Not<F>(F);
+
+impl<F: PixelFilter> PixelFilter for Not<F> {
+    fn accepts(&self, pixel: &Rgba<u8>) -> bool {
+        !self.0.accepts(pixel)
+    }
+}
+
+/// Accepts all pixels
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AcceptAll;
+
+impl PixelFilter for AcceptAll {
+    fn accepts(&self, _: &Rgba<u8>) -> bool {
+        true
+    }
+}
+
+/// Accepts pixels with an alpha of at least the given value
+#[derive(Debug, Clone, Copy)]
+pub struct MinAlpha(pub u8);
+
+impl PixelFilter for MinAlpha {
+    fn accepts(&self, pixel: &Rgba<u8>) -> bool {
+        pixel[3] >= self.0
+    }
+}
+
+/// Rejects pixels whose channels are all above the given value
+#[derive(Debug, Clone, Copy)]
+pub struct RejectNearWhite(pub u8);
+
+impl PixelFilter for RejectNearWhite {
+    fn accepts(&self, pixel: &Rgba<u8>) -> bool {
+        let [r, g, b, _] = pixel.0;
+        !(r > self.0 && g > self.0 && b > self.0)
+    }
+}
+
+/// Rejects pixels whose channels are all below the given value
+#[derive(Debug, Clone, Copy)]
+pub struct RejectNearBlack(pub u8);
+
+impl PixelFilter for RejectNearBlack {
+    fn accepts(&self, pixel: &Rgba<u8>) -> bool {
+        let [r, g, b, _] = pixel.0;
+        !(r < self.0 && g < self.0 && b < self.0)
+    }
+}
+
+/// Rejects pixels with an HSL saturation below the given value
+#[derive(Debug, Clone, Copy)]
+pub struct RejectGray(pub f64);
+
+impl PixelFilter for RejectGray {
+    fn accepts(&self, pixel: &Rgba<u8>) -> bool {
+        HSL::from_rgb(&pixel.0[..3]).s >= self.0
+    }
+}
+
+/// Rejects pixels close to the red I-line of the Android palette library
+///
+/// These are unsaturated orange tones, which are common in skin.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RejectILine;
+
+impl PixelFilter for RejectILine {
+    fn accepts(&self, pixel: &Rgba<u8>) -> bool {
+        let HSL { h, s, .. } = HSL::from_rgb(&pixel.0[..3]);
+        !((10_f64..=37_f64).contains(&h) && s <= 0.82)
+    }
+}
+
+/// Filter used by [`Palette::from_image`](crate::Palette::from_image)
+///
+/// Accepts mostly opaque pixels which are not white.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DefaultFilter;
+
+impl PixelFilter for DefaultFilter {
+    fn accepts(&self, pixel: &Rgba<u8>) -> bool {
+        const MIN_ALPHA: u8 = 125;
+        const MAX_COLOR: u8 = 250;
+
+        MinAlpha(MIN_ALPHA)
+            .and(RejectNearWhite(MAX_COLOR))
+            .accepts(pixel)
+    }
+}
+
+/// Filter of the Android palette library
+///
+/// Rejects colors with an HSL lightness of at most 5% or at least 95% and colors close to the red
+/// I-line. Like Android the alpha channel is ignored.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AndroidFilter;
+
+impl PixelFilter for AndroidFilter {
+    fn accepts(&self, pixel: &Rgba<u8>) -> bool {
+        const BLACK_MAX_LIGHTNESS: f64 = 0.05;
+        const WHITE_MIN_LIGHTNESS: f64 = 0.95;
+
+        let HSL { l, .. } = HSL::from_rgb(&pixel.0[..3]);
+        l > BLACK_MAX_LIGHTNESS && l < WHITE_MIN_LIGHTNESS && RejectILine.accepts(pixel)
+    }
+}
+
+/// Selects one of the built-in filters at runtime
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum FilterPreset {
+    /// The [`DefaultFilter`] of this crate
+    #[default]
+    Default,
+    /// The [`AndroidFilter`], to match the colors of the Android palette library
+    Android,
+}
+
+impl PixelFilter for FilterPreset {
+    fn accepts(&self, pixel: &Rgba<u8>) -> bool {
+        match self {
+            FilterPreset::Default => DefaultFilter.accepts(pixel),
+            FilterPreset::Android => AndroidFilter.accepts(pixel),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OPAQUE: Rgba<u8> = Rgba([10, 20, 30, 255]);
+    const TRANSLUCENT: Rgba<u8> = Rgba([10, 20, 30, 100]);
+
+    fn is_red(pixel: &Rgba<u8>) -> bool {
+        pixel[0] > 128
+    }
+
+    #[test]
+    fn combinators_follow_truth_tables() {
+        let red = Rgba([200, 20, 30, 255]);
+        let cases = [
+            (OPAQUE, true, false),
+            (TRANSLUCENT, false, false),
+            (red, true, true),
+        ];
+        for (pixel, opaque, red) in cases {
+            assert_eq!(MinAlpha(128).accepts(&pixel), opaque);
+            assert_eq!(is_red.accepts(&pixel), red);
+            assert_eq!(MinAlpha(128).and(is_red).accepts(&pixel), opaque && red);
+            assert_eq!(MinAlpha(128).or(is_red).accepts(&pixel), opaque || red);
+            assert_eq!(MinAlpha(128).not().accepts(&pixel), !opaque);
+            assert_eq!(
+                MinAlpha(128).and(is_red.not()).accepts(&pixel),
+                opaque && !red
+            );
+        }
+        assert!(AcceptAll.accepts(&TRANSLUCENT));
+        assert!(!AcceptAll.not().accepts(&OPAQUE));
+    }
+
+    #[test]
+    fn closures_are_filters() {
+        let max_green = 25;
+        let filter = |pixel: &Rgba<u8>| pixel[1] <= max_green;
+        assert!(filter.accepts(&OPAQUE));
+        assert!(!filter.accepts(&Rgba([0, 26, 0, 255])));
+        assert!(!filter.and(MinAlpha(128)).accepts(&TRANSLUCENT));
+    }
+
+    fn opaque(r: u8, g: u8, b: u8) -> Rgba<u8> {
+        Rgba([r, g, b, u8::MAX])
+    }
+
+    #[test]
+    fn android_rejects_black_and_white() {
+        let gray = |v| opaque(v, v, v);
+        // Integer lightnesses are never exactly 0.05 or 0.95
+        assert!(!AndroidFilter.accepts(&gray(0)));
+        assert!(!AndroidFilter.accepts(&gray(12)));
+        assert!(AndroidFilter.accepts(&gray(13)));
+        assert!(AndroidFilter.accepts(&gray(242)));
+        assert!(!AndroidFilter.accepts(&gray(243)));
+        assert!(!AndroidFilter.accepts(&gray(u8::MAX)));
+    }
+
+    #[test]
+    fn i_line_has_android_bounds() {
+        // Hues of 8.98°, 10.39°, 36.38° and 38.27° with a saturation of 0.498
+        assert!(RejectILine.accepts(&opaque(191, 83, 64)));
+        assert!(!RejectILine.accepts(&opaque(191, 86, 64)));
+        assert!(!RejectILine.accepts(&opaque(191, 141, 64)));
+        assert!(RejectILine.accepts(&opaque(191, 145, 64)));
+        // Saturations of 0.8196 and 0.8275 with a hue of about 20°
+        assert!(!RejectILine.accepts(&opaque(232, 93, 23)));
+        assert!(RejectILine.accepts(&opaque(233, 92, 22)));
+
+        assert!(!AndroidFilter.accepts(&opaque(191, 86, 64)));
+        assert!(AndroidFilter.accepts(&opaque(233, 92, 22)));
+    }
+
+    #[test]
+    fn android_ignores_alpha() {
+        assert!(AndroidFilter.accepts(&Rgba([50, 100, 200, 0])));
+        assert!(!AndroidFilter.accepts(&Rgba([191, 86, 64, 0])));
+        assert!(!AndroidFilter.accepts(&Rgba([5, 5, 5, 0])));
+    }
+
+    #[test]
+    fn presets_dispatch_to_their_filter() {
+        // Near-black is only rejected by Android, transparency only by the default filter
+        let pixels = [
+            opaque(5, 5, 5),
+            Rgba([50, 100, 200, 0]),
+            opaque(u8::MAX, u8::MAX, u8::MAX),
+            opaque(191, 86, 64),
+        ];
+        for pixel in pixels {
+            assert_eq!(
+                FilterPreset::Default.accepts(&pixel),
+                DefaultFilter.accepts(&pixel)
+            );
+            assert_eq!(
+                FilterPreset::Android.accepts(&pixel),
+                AndroidFilter.accepts(&pixel)
+            );
+        }
+        assert!(FilterPreset::Default.accepts(&pixels[0]));
+        assert!(!FilterPreset::Android.accepts(&pixels[0]));
+        assert!(!FilterPreset::Default.accepts(&pixels[1]));
+        assert!(FilterPreset::Android.accepts(&pixels[1]));
+        assert_eq!(FilterPreset::default(), FilterPreset::Default);
+    }
+}
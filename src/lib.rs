@@ -5,16 +5,38 @@
 //!
 //! [vibrant-js]: https://github.com/jariz/vibrant.js
 //! [color_quant]: https://github.com/PistonDevelopers/color_quant
+//!
+//! The `rayon` feature samples, filters and counts the pixels of [`MedianCut`] and [`Neu`] on
+//! multiple threads with the same results.
 
 #![cfg_attr(feature = "dev", feature(plugin))]
 #![cfg_attr(feature = "dev", plugin(clippy))]
 #![deny(missing_docs)]
+#![forbid(unsafe_code)]
 
+pub use border::{Border, BorderColor};
+pub use contrast::{TextSize, WcagLevel};
+pub use filter::{
+    AcceptAll, And, AndroidFilter, DefaultFilter, FilterPreset, MinAlpha, Not, Or, PixelFilter,
+    RejectGray, RejectILine, RejectNearBlack, RejectNearWhite,
+};
 pub use palette::Palette;
 pub use quantizer::*;
-pub use vibrant::Vibrancy;
+pub use saliency::saliency_map;
+pub use swatch::Swatch;
+pub use target::{Candidate, ColorSpace, MinMaxTarget, Score, Selector, Target, Weights};
+pub use vibrant::{Vibrancy, VibrancyCandidates, VibrancyConfig};
 
+mod border;
+mod contrast;
+mod filter;
+mod oklab;
 mod palette;
 mod quantizer;
+mod saliency;
 mod settings;
+mod swatch;
+mod target;
+#[cfg(test)]
+mod testing;
 mod vibrant;
@@ -0,0 +1,81 @@
+use image::Rgb;
+
+/// Color in the perceptual OKLab color space
+///
+/// See <https://bottosson.github.io/posts/oklab/>.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub(crate) struct Oklab {
+    pub l: f64,
+    pub a: f64,
+    pub b: f64,
+}
+
+fn to_linear(c: u8) -> f64 {
+    let c = c as f64 / 255_f64;
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn from_linear(c: f64) -> u8 {
+    let c = if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1_f64 / 2.4) - 0.055
+    };
+    (c * 255_f64).round().clamp(0_f64, 255_f64) as u8
+}
+
+impl Oklab {
+    pub fn from_rgb(color: &Rgb<u8>) -> Self {
+        let [r, g, b] = color.0.map(to_linear);
+
+        let l = (0.4122214708 * r + 0.5363325363 * g + 0.0514459929 * b).cbrt();
+        let m = (0.2119034982 * r + 0.6806995451 * g + 0.1073969566 * b).cbrt();
+        let s = (0.0883024619 * r + 0.2817188376 * g + 0.6299787005 * b).cbrt();
+
+        Self {
+            l: 0.2104542553 * l + 0.7936177850 * m - 0.0040720468 * s,
+            a: 1.9779984951 * l - 2.4285922050 * m + 0.4505937099 * s,
+            b: 0.0259040371 * l + 0.7827717662 * m - 0.8086757660 * s,
+        }
+    }
+
+    /// Linear sRGB channels, outside of 0 to 1 if the color is outside of the gamut
+    fn to_linear_rgb(self) -> [f64; 3] {
+        let l = (self.l + 0.3963377774 * self.a + 0.2158037573 * self.b).powi(3);
+        let m = (self.l - 0.1055613458 * self.a - 0.0638541728 * self.b).powi(3);
+        let s = (self.l - 0.0894841775 * self.a - 1.2914855480 * self.b).powi(3);
+
+        [
+            4.0767416621 * l - 3.3077115913 * m + 0.2309699292 * s,
+            -1.2684380046 * l + 2.6097574011 * m - 0.3413193965 * s,
+            -0.0041960863 * l - 0.7034186147 * m + 1.7076147010 * s,
+        ]
+    }
+
+    /// Whether the color is an sRGB color, up to rounding errors
+    pub fn in_gamut(self) -> bool {
+        const EPSILON: f64 = 1e-6;
+        self.to_linear_rgb()
+            .iter()
+            .all(|c| (-EPSILON..=1_f64 + EPSILON).contains(c))
+    }
+
+    /// Nearest sRGB color, colors outside of the gamut are clipped
+    pub fn to_rgb(self) -> Rgb<u8> {
+        Rgb(self.to_linear_rgb().map(from_linear))
+    }
+
+    /// Euclidean distance, the ΔE of OKLab
+    pub fn distance(&self, other: &Oklab) -> f64 {
+        self.distance_squared(other).sqrt()
+    }
+
+    pub fn distance_squared(&self, other: &Oklab) -> f64 {
+        let (l, a, b) = (self.l - other.l, self.a - other.a, self.b - other.b);
+        l * l + a * a + b * b
+    }
+}
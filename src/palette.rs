@@ -3,7 +3,7 @@ use std::fmt;
 use image::{GenericImageView, Pixel, Rgba};
 use itertools::Itertools;
 
-use crate::{Color, Error, Quantizer};
+use crate::{Color, DefaultFilter, Error, PixelConfig, PixelFilter, Quantizer};
 
 /// Palette of colors.
 #[derive(Debug, Default)]
@@ -14,21 +14,58 @@ pub struct Palette {
 
 impl Palette {
     /// Create a new palette from an image
+    ///
+    /// Uses the [`DefaultFilter`]. Fails with [`Error::NoPixels`] if the image has no interesting
+    /// pixels, e.g. because it is fully transparent or white.
     pub fn from_image<P, G, Q>(
         image: &G,
         color_count: usize,
-        quality: u32,
+        config: &PixelConfig,
         quantizer: &Q,
     ) -> Result<Palette, Error>
     where
         P: Pixel<Subpixel = u8> + 'static,
-        G: GenericImageView<Pixel = P>,
+        G: GenericImageView<Pixel = P> + Sync,
         Q: Quantizer,
     {
-        let palette = quantizer.quantize(image, color_count, quality, is_interesting_pixel)?;
+        Self::from_image_with_filter(image, color_count, config, quantizer, DefaultFilter)
+    }
+
+    /// Create a new palette from the pixels of an image accepted by the filter
+    ///
+    /// Only the pixels selected by the config are used, and the populations of the colors are
+    /// weighted by its weighting.
+    pub fn from_image_with_filter<P, G, Q, F>(
+        image: &G,
+        color_count: usize,
+        config: &PixelConfig,
+        quantizer: &Q,
+        filter: F,
+    ) -> Result<Palette, Error>
+    where
+        P: Pixel<Subpixel = u8> + 'static,
+        G: GenericImageView<Pixel = P> + Sync,
+        Q: Quantizer,
+        F: PixelFilter + Sync,
+    {
+        let palette = quantizer.quantize(image, color_count, config, |pixel: &Rgba<u8>| {
+            filter.accepts(pixel)
+        })?;
         Ok(Self { palette })
     }
 
+    /// Remove the colors rejected by the filter
+    ///
+    /// Unlike filtering pixels this does not influence the quantization, so clusters which average
+    /// to e.g. gray or white can be removed without changing the other clusters. The colors are
+    /// passed to the filter as opaque pixels.
+    pub fn retain_swatches<F: PixelFilter>(&mut self, filter: F) {
+        self.palette.retain(|color| {
+            let [r, g, b] = color.color.0;
+            filter.accepts(&Rgba([r, g, b, u8::MAX]))
+        });
+    }
+
     /// Change ordering of colors in palette to be of frequency using the pixel count.
     pub fn into_sorted_by_frequency(mut self) -> Self {
         self.palette.sort_by_key(|value| value.population);
@@ -36,16 +73,6 @@ impl Palette {
     }
 }
 
-fn is_interesting_pixel(pixel: &Rgba<u8>) -> bool {
-    let (r, g, b, a) = (pixel[0], pixel[1], pixel[2], pixel[3]);
-
-    // If pixel is mostly opaque and not white
-    const MIN_ALPHA: u8 = 125;
-    const MAX_COLOR: u8 = 250;
-
-    (a >= MIN_ALPHA) && !(r > MAX_COLOR && g > MAX_COLOR && b > MAX_COLOR)
-}
-
 impl fmt::Display for Palette {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let color_list = self
@@ -67,3 +94,47 @@ impl fmt::Display for Color {
         write!(f, ", {} pixels", self.population)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgba, RgbaImage};
+
+    use super::*;
+    use crate::testing::color;
+    use crate::{KMeans, MedianCut, Neu, Octree, RejectGray, Vibrancy, Wu};
+
+    fn assert_no_pixels<Q: Quantizer>(quantizer: &Q, color_count: usize) {
+        let transparent = RgbaImage::from_pixel(16, 16, Rgba([200, 100, 50, 0]));
+        let white = RgbaImage::from_pixel(16, 16, Rgba([u8::MAX; 4]));
+        for image in [transparent, white] {
+            let palette =
+                Palette::from_image(&image, color_count, &PixelConfig::default(), quantizer);
+            assert!(matches!(palette, Err(Error::NoPixels)), "{:?}", palette);
+        }
+    }
+
+    #[test]
+    fn images_without_pixels_fail() {
+        assert_no_pixels(&Neu::default(), 64);
+        assert_no_pixels(&MedianCut::default(), 8);
+        assert_no_pixels(&Wu, 8);
+        assert_no_pixels(&KMeans::default(), 8);
+        assert_no_pixels(&Octree::default(), 8);
+    }
+
+    #[test]
+    fn empty_palette_has_no_vibrant_colors() {
+        assert_eq!(Vibrancy::from_palette(&[]), Vibrancy::default());
+    }
+
+    #[test]
+    fn retain_swatches_drops_gray_clusters() {
+        let red = color(0xC82828, 10);
+        let blue = color(0x283CC8, 30);
+        let mut palette = Palette {
+            palette: vec![red, color(0x808280, 100), blue],
+        };
+        palette.retain_swatches(RejectGray(0.1));
+        assert_eq!(palette.palette, [red, blue]);
+    }
+}
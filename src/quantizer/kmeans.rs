@@ -0,0 +1,366 @@
+use std::collections::HashMap;
+use std::ops::Range;
+
+use image::{GenericImageView, Pixel, Rgb, Rgba};
+
+use super::weighting;
+use crate::oklab::Oklab;
+use crate::{Color, Error, PixelConfig, Quantizer, Wu};
+
+/// K-means quantizer clustering in the OKLab color space
+///
+/// The clusters are either seeded with k-means++ or with the colors of another quantizer, e.g.
+/// [`Wu`] for the quantizer of Celebi.
+#[derive(Debug, Clone)]
+pub struct KMeans<Q = Wu> {
+    /// Quantizer providing the initial clusters, k-means++ is used if `None`, if it returns no
+    /// colors or if it doesn't support the number of colors, like [`Neu`](crate::Neu) below 64
+    pub seeds: Option<Q>,
+    /// Maximum number of iterations
+    pub max_iterations: usize,
+    /// Stop once no cluster moved further than this OKLab distance in an iteration
+    pub convergence: f64,
+    /// Seed of the random number generator used by k-means++
+    pub random_seed: u64,
+}
+
+impl Default for KMeans {
+    fn default() -> Self {
+        Self {
+            seeds: None,
+            max_iterations: 16,
+            convergence: 1e-3,
+            random_seed: 0x5EED,
+        }
+    }
+}
+
+impl<Q: Quantizer> KMeans<Q> {
+    /// Start with the colors of `quantizer` instead of k-means++
+    pub fn with_seeds(quantizer: Q) -> Self {
+        let KMeans {
+            max_iterations,
+            convergence,
+            random_seed,
+            ..
+        } = KMeans::default();
+        Self {
+            seeds: Some(quantizer),
+            max_iterations,
+            convergence,
+            random_seed,
+        }
+    }
+}
+
+/// SplitMix64, a small random number generator so results are reproducible across platforms
+struct Random(u64);
+
+impl Random {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniformly distributed in `[0, 1)`
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1_u64 << 53) as f64
+    }
+
+    /// Index chosen with a probability proportional to its weight
+    fn weighted_index(&mut self, weights: impl Iterator<Item = f64> + Clone) -> Option<usize> {
+        let total: f64 = weights.clone().sum();
+        if total <= 0_f64 {
+            return None;
+        }
+        let mut threshold = self.next_f64() * total;
+        let mut last = None;
+        for (i, weight) in weights.enumerate() {
+            if weight > 0_f64 {
+                if threshold < weight {
+                    return Some(i);
+                }
+                threshold -= weight;
+                last = Some(i);
+            }
+        }
+        last
+    }
+}
+
+/// Distinct color with its weighted pixel count
+struct Sample {
+    color: Oklab,
+    count: u64,
+}
+
+fn nearest(centers: &[Oklab], color: &Oklab) -> (usize, f64) {
+    centers
+        .iter()
+        .map(|center| center.distance_squared(color))
+        .enumerate()
+        .min_by(|(_, a), (_, b)| a.total_cmp(b))
+        .unwrap()
+}
+
+/// Pick initial clusters with k-means++
+fn plus_plus(samples: &[Sample], colors: usize, random: &mut Random) -> Vec<Oklab> {
+    let mut centers = Vec::with_capacity(colors);
+    let first = random
+        .weighted_index(samples.iter().map(|s| s.count as f64))
+        .unwrap();
+    centers.push(samples[first].color);
+    let mut distances = samples
+        .iter()
+        .map(|s| s.color.distance_squared(&centers[0]))
+        .collect::<Vec<_>>();
+    while centers.len() < colors {
+        let weights = samples
+            .iter()
+            .zip(&distances)
+            .map(|(s, distance)| s.count as f64 * distance);
+        let next = match random.weighted_index(weights) {
+            Some(next) => samples[next].color,
+            // All colors are centers already
+            None => break,
+        };
+        for (distance, sample) in distances.iter_mut().zip(samples) {
+            *distance = distance.min(sample.color.distance_squared(&next));
+        }
+        centers.push(next);
+    }
+    centers
+}
+
+const COLOR_RANGE: Range<usize> = 2..257;
+
+impl<Q: Quantizer> Quantizer for KMeans<Q> {
+    fn quantize<I, P, F>(
+        &self,
+        image: &I,
+        colors: usize,
+        config: &PixelConfig,
+        filter: F,
+    ) -> Result<Vec<Color>, Error>
+    where
+        P: Pixel<Subpixel = u8> + 'static,
+        I: GenericImageView<Pixel = P> + Sync,
+        F: Fn(&Rgba<u8>) -> bool + Sync,
+    {
+        if !COLOR_RANGE.contains(&colors) {
+            return Err(Error::ColorCountOutOfBounds(colors, COLOR_RANGE));
+        }
+
+        let seeds = match &self.seeds {
+            Some(quantizer) => match quantizer.quantize(image, colors, config, &filter) {
+                Ok(seeds) => seeds,
+                Err(Error::ColorCountOutOfBounds(..)) => Vec::new(),
+                Err(error) => return Err(error),
+            },
+            None => Vec::new(),
+        };
+
+        let mut counts = HashMap::<[u8; 3], u64>::new();
+        config.pixels(image, filter)?.for_each(|pixel, weight| {
+            let [r, g, b, _] = pixel.0;
+            *counts.entry([r, g, b]).or_default() += weight;
+        });
+        if counts.is_empty() {
+            return Err(Error::NoPixels);
+        }
+        let mut samples = counts
+            .into_iter()
+            .map(|(color, count)| Sample {
+                color: Oklab::from_rgb(&Rgb(color)),
+                count,
+            })
+            .collect::<Vec<_>>();
+        // The order of the hash map is random
+        samples.sort_unstable_by(|a, b| {
+            (a.color.l, a.color.a, a.color.b)
+                .partial_cmp(&(b.color.l, b.color.a, b.color.b))
+                .unwrap()
+        });
+
+        // Seeds without colors fall back to k-means++
+        let mut centers = if seeds.is_empty() {
+            plus_plus(&samples, colors, &mut Random(self.random_seed))
+        } else {
+            seeds
+                .iter()
+                .map(|color| Oklab::from_rgb(&color.color))
+                .collect()
+        };
+
+        let mut assignments = vec![0; samples.len()];
+        for _ in 0..self.max_iterations {
+            for (assignment, sample) in assignments.iter_mut().zip(&samples) {
+                *assignment = nearest(&centers, &sample.color).0;
+            }
+
+            let mut sums = vec![(Oklab::default(), 0_u64); centers.len()];
+            for (&assignment, sample) in assignments.iter().zip(&samples) {
+                let (sum, count) = &mut sums[assignment];
+                let weight = sample.count as f64;
+                sum.l += sample.color.l * weight;
+                sum.a += sample.color.a * weight;
+                sum.b += sample.color.b * weight;
+                *count += sample.count;
+            }
+
+            let mut max_shift = 0_f64;
+            for (center, (sum, count)) in centers.iter_mut().zip(&sums) {
+                // Empty clusters keep their position
+                if *count == 0 {
+                    continue;
+                }
+                let count = *count as f64;
+                let mean = Oklab {
+                    l: sum.l / count,
+                    a: sum.a / count,
+                    b: sum.b / count,
+                };
+                max_shift = max_shift.max(mean.distance(center));
+                *center = mean;
+            }
+            if max_shift < self.convergence {
+                break;
+            }
+        }
+
+        let mut populations = vec![0_u64; centers.len()];
+        for sample in &samples {
+            populations[nearest(&centers, &sample.color).0] += sample.count;
+        }
+        Ok(centers
+            .iter()
+            .zip(populations)
+            .filter(|&(_, population)| population != 0)
+            .map(|(center, population)| Color {
+                color: center.to_rgb(),
+                population: weighting::population(population),
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::RgbImage;
+
+    use super::*;
+    use crate::quantizer::testing::{quantize, xorshift};
+    use crate::Neu;
+
+    /// Three noisy blocks of 16 by 16 pixels
+    fn blocks() -> RgbImage {
+        let mut random = xorshift(0x2545_F491);
+        RgbImage::from_fn(48, 16, |x, _| {
+            let color = [[200, 40, 40], [40, 160, 60], [50, 70, 210]][x as usize / 16];
+            Rgb(color.map(|c| c + (random() % 31) as u8 - 15))
+        })
+    }
+
+    /// Quantizer which never finds any colors
+    struct Empty;
+
+    impl Quantizer for Empty {
+        fn quantize<I, P, F>(
+            &self,
+            _: &I,
+            _: usize,
+            _: &PixelConfig,
+            _: F,
+        ) -> Result<Vec<Color>, Error>
+        where
+            P: Pixel<Subpixel = u8> + 'static,
+            I: GenericImageView<Pixel = P>,
+            F: FnMut(&Rgba<u8>) -> bool,
+        {
+            Ok(Vec::new())
+        }
+    }
+
+    #[test]
+    fn empty_seeds_fall_back_to_plus_plus() {
+        let image = RgbImage::from_fn(8, 8, |x, _| Rgb([x as u8 * 30, 0, 0]));
+        let colors = KMeans::with_seeds(Empty)
+            .quantize(&image, 4, &PixelConfig::default(), |_: &Rgba<u8>| true)
+            .unwrap();
+        assert_eq!(colors.len(), 4);
+        assert_eq!(colors.iter().map(|c| c.population).sum::<usize>(), 64);
+    }
+
+    #[test]
+    fn wu_seeds_converge_to_blocks() {
+        let kmeans = KMeans::with_seeds(Wu);
+        let mut colors = quantize(&kmeans, &blocks(), 3);
+        colors.sort_unstable_by_key(|c| c.color.0);
+        assert_eq!(
+            colors.iter().map(|c| c.population).collect::<Vec<_>>(),
+            [256; 3]
+        );
+        for (color, expected) in colors
+            .iter()
+            .zip([[40, 160, 60], [50, 70, 210], [200, 40, 40]])
+        {
+            for (c, e) in color.color.0.iter().zip(expected) {
+                assert!(c.abs_diff(e) <= 4, "{:?}", color.color);
+            }
+        }
+        // More iterations don't move the clusters anymore
+        let more = KMeans {
+            max_iterations: 100,
+            ..KMeans::with_seeds(Wu)
+        };
+        let mut more_colors = quantize(&more, &blocks(), 3);
+        more_colors.sort_unstable_by_key(|c| c.color.0);
+        assert_eq!(more_colors, colors);
+    }
+
+    #[test]
+    fn random_seed_is_reproducible() {
+        let image = blocks();
+        let kmeans = |random_seed| KMeans {
+            random_seed,
+            // Only the initial clusters
+            max_iterations: 0,
+            ..KMeans::default()
+        };
+        assert_eq!(
+            quantize(&kmeans(1), &image, 8),
+            quantize(&kmeans(1), &image, 8)
+        );
+        assert_ne!(
+            quantize(&kmeans(1), &image, 8),
+            quantize(&kmeans(2), &image, 8)
+        );
+    }
+
+    #[test]
+    fn iterations_stop_at_limit_or_convergence() {
+        let image = blocks();
+        let kmeans = |max_iterations, convergence| {
+            let kmeans = KMeans {
+                max_iterations,
+                convergence,
+                ..KMeans::default()
+            };
+            quantize(&kmeans, &image, 8)
+        };
+        let once = kmeans(1, 0_f64);
+        assert_ne!(kmeans(0, 0_f64), once);
+        assert_ne!(kmeans(16, 0_f64), once);
+        // Any shift is below an infinite convergence, so it stops after the first iteration
+        assert_eq!(kmeans(16, f64::INFINITY), once);
+    }
+
+    #[test]
+    fn unsupported_seed_counts_fall_back_to_plus_plus() {
+        let colors = quantize(&KMeans::with_seeds(Neu::default()), &blocks(), 8);
+        assert_eq!(colors.len(), 8);
+    }
+}
@@ -1,17 +1,11 @@
-use std::cmp::{Ordering, Reverse};
+use std::cmp::Ordering;
 use std::collections::BinaryHeap;
-use std::marker::PhantomData;
-use std::mem::ManuallyDrop;
 use std::ops::Range;
 
-use image::{
-    imageops::{resize, FilterType},
-    GenericImageView, Pixel, Rgba,
-};
+use image::{GenericImageView, Pixel, Rgba};
 
-use crate::{Color, Error, Quantizer};
-
-const BITS: usize = 5;
+use super::weighting;
+use crate::{Color, Error, PixelConfig, Quantizer};
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
 struct Rgb<T> {
@@ -21,6 +15,22 @@ struct Rgb<T> {
 }
 
 impl<T> Rgb<T> {
+    fn get(&self, dimension: Dimension) -> &T {
+        match dimension {
+            Dimension::R => &self.r,
+            Dimension::G => &self.g,
+            Dimension::B => &self.b,
+        }
+    }
+
+    fn get_mut(&mut self, dimension: Dimension) -> &mut T {
+        match dimension {
+            Dimension::R => &mut self.r,
+            Dimension::G => &mut self.g,
+            Dimension::B => &mut self.b,
+        }
+    }
+
     fn map<O>(self, mut f: impl FnMut(T) -> O) -> Rgb<O> {
         Rgb {
             r: f(self.r),
@@ -48,9 +58,7 @@ impl<T> Rgb<T> {
 
 impl Rgb<u8> {
     fn into_image_rgb(self) -> image::Rgb<u8> {
-        image::Rgb {
-            0: [self.r, self.g, self.b],
-        }
+        image::Rgb([self.r, self.g, self.b])
     }
 }
 
@@ -88,17 +96,18 @@ impl MinMax<Quantized> {
 struct Quantized(u8);
 
 impl Quantized {
-    fn from_color(color: u8) -> Self {
-        Self(color >> (8 - BITS))
+    fn from_color(color: u8, bits: usize) -> Self {
+        Self(color >> (8 - bits))
     }
 
-    fn from_value_unchecked(value: usize) -> Self {
-        debug_assert!(value < 1 << BITS);
+    fn from_value_unchecked(value: usize, bits: usize) -> Self {
+        debug_assert!(value < 1 << bits);
         Self(value as u8)
     }
 
-    fn to_color(self) -> u8 {
-        self.0 << (8 - BITS)
+    /// Center of the range of colors of this bucket
+    fn to_center(self, bits: usize) -> f64 {
+        (self.0 as f64 + 0.5) * (1 << (8 - bits)) as f64
     }
 
     fn as_usize(&self) -> usize {
@@ -107,80 +116,81 @@ impl Quantized {
 }
 
 impl Rgb<Quantized> {
-    fn as_color_index(&self) -> usize {
+    fn as_color_index(&self, bits: usize) -> usize {
         let &Rgb { r, g, b } = self;
-        (r.as_usize() << (2 * BITS)) | (g.as_usize() << BITS) | b.as_usize()
+        (r.as_usize() << (2 * bits)) | (g.as_usize() << bits) | b.as_usize()
     }
 }
 
 struct Histogram {
-    buckets: Vec<u32>,
+    /// Weighted population of the colors
+    buckets: Vec<u64>,
+    /// Bits per channel
+    bits: usize,
 }
 
 impl Histogram {
-    fn new() -> Self {
+    fn new(bits: usize) -> Self {
         Self {
-            buckets: vec![0; 1 << (3 * BITS)],
+            buckets: vec![0; 1 << (3 * bits)],
+            bits,
         }
     }
 
-    fn from_image<F: FnMut(&Rgba<u8>) -> bool>(
-        image: impl IntoIterator<Item = Rgba<u8>>,
-        f: F,
-    ) -> (Self, Vec<Rgb<Quantized>>) {
-        let mut histogram = Self::new();
-        let iter = image.into_iter().filter(f).map(|color| {
-            let [r, g, b, _] = color.0;
-            Rgb { r, g, b }.map(Quantized::from_color)
-        });
-
-        for color in iter {
-            histogram.insert(&color);
-        }
-
-        let unique_colors = histogram.counts().filter(|v| v != &0).count();
+    fn distinct_colors(&self) -> Vec<Rgb<Quantized>> {
+        let unique_colors = self.counts().filter(|v| v != &0).count();
         let mut unique_colors = Vec::with_capacity(unique_colors);
         unique_colors.extend(
-            histogram
-                .buckets()
+            self.buckets()
                 .filter(|(_, count)| count != &0)
                 .map(|(color, _)| color),
         );
-        (histogram, unique_colors)
+        unique_colors
     }
 
-    fn counts(&self) -> impl Iterator<Item = u32> + '_ {
+    fn counts(&self) -> impl Iterator<Item = u64> + '_ {
         self.buckets.iter().copied()
     }
 
-    fn buckets(&self) -> impl Iterator<Item = (Rgb<Quantized>, u32)> + '_ {
-        self.buckets.iter().enumerate().map(|(color, &count)| {
-            const MASK: usize = 0xFF >> (8 - BITS);
+    fn buckets(&self) -> impl Iterator<Item = (Rgb<Quantized>, u64)> + '_ {
+        let bits = self.bits;
+        let mask = 0xFF >> (8 - bits);
+        self.buckets.iter().enumerate().map(move |(color, &count)| {
             (
                 Rgb {
-                    r: Quantized::from_value_unchecked(color >> 2 * BITS),
-                    g: Quantized::from_value_unchecked((color >> BITS) & MASK),
-                    b: Quantized::from_value_unchecked(color & MASK),
+                    r: Quantized::from_value_unchecked(color >> (2 * bits), bits),
+                    g: Quantized::from_value_unchecked((color >> bits) & mask, bits),
+                    b: Quantized::from_value_unchecked(color & mask, bits),
                 },
                 count,
             )
         })
     }
 
-    fn insert(&mut self, color: &Rgb<Quantized>) {
-        let index = color.as_color_index();
-        self.buckets[index] += 1;
+    fn insert(&mut self, pixel: &Rgba<u8>, weight: u64) {
+        let [r, g, b, _] = pixel.0;
+        let color = Rgb { r, g, b }.map(|c| Quantized::from_color(c, self.bits));
+        self.buckets[color.as_color_index(self.bits)] += weight;
     }
 
-    fn count_of(&self, color: &Rgb<Quantized>) -> u32 {
-        let index = color.as_color_index();
+    /// Adds the populations of another histogram with the same bits
+    #[cfg(feature = "rayon")]
+    fn merge(mut self, other: Self) -> Self {
+        for (count, other) in self.buckets.iter_mut().zip(other.buckets) {
+            *count += other;
+        }
+        self
+    }
+
+    fn count_of(&self, color: &Rgb<Quantized>) -> u64 {
+        let index = color.as_color_index(self.bits);
         self.buckets[index]
     }
 
     fn colors<'a>(
         &'a self,
         colors: &'a [Rgb<Quantized>],
-    ) -> impl Iterator<Item = (Rgb<Quantized>, u32)> + 'a {
+    ) -> impl Iterator<Item = (Rgb<Quantized>, u64)> + 'a {
         colors.iter().cloned().map(move |color| {
             let count = self.count_of(&color);
             (color, count)
@@ -188,8 +198,10 @@ impl Histogram {
     }
 }
 
+#[derive(Clone)]
 struct Bounds(Rgb<MinMax<Quantized>>);
 
+#[derive(Debug, Copy, Clone)]
 enum Dimension {
     R,
     G,
@@ -226,7 +238,7 @@ impl Bounds {
 struct VBox<'a> {
     bounds: Bounds,
     colors: &'a mut [Rgb<Quantized>],
-    population: u32,
+    population: u64,
 }
 
 impl<'a> VBox<'a> {
@@ -247,25 +259,35 @@ impl<'a> VBox<'a> {
         }
     }
 
+    fn with_bounds(
+        bounds: Bounds,
+        colors: &'a mut [Rgb<Quantized>],
+        histogram: &Histogram,
+    ) -> Self {
+        let population = histogram.colors(colors).map(|(_, count)| count).sum();
+        Self {
+            bounds,
+            colors,
+            population,
+        }
+    }
+
+    /// Average of the colors, using the center of the buckets like MMCQ
     fn average(&self, histogram: &Histogram) -> Color {
-        let init = Rgb::<usize>::default();
-        dbg!(&self.colors);
+        let init = Rgb::<f64>::default();
         let color = histogram
             .colors(self.colors)
             .fold(init, |acc_c, (v_c, v_p)| {
-                let color = acc_c
+                acc_c
                     .zip(v_c)
-                    .map(|(a, b)| a + v_p as usize * b.to_color() as usize);
-                color
+                    .map(|(a, b)| a + v_p as f64 * b.to_center(histogram.bits))
             });
-        dbg!(self.population);
         let color = color
-            .map(|c| ((c as f64 / self.population as f64).round() as u8))
+            .map(|c| (c / self.population as f64) as u8)
             .into_image_rgb();
-        dbg!(color);
         Color {
             color,
-            population: self.population as usize,
+            population: weighting::population(self.population),
         }
     }
 
@@ -273,188 +295,453 @@ impl<'a> VBox<'a> {
         self.bounds.volume()
     }
 
+    /// Split the box along its longest dimension like the MMCQ of color-thief
+    ///
+    /// The cut is placed around the position where the cumulative population crosses half of the
+    /// population, going further into the larger side. The bounds of the halves are not shrunk to
+    /// their colors.
+    ///
+    /// Unlike MMCQ no empty box is created if all colors are in the last slice. The box is shrunk
+    /// to its colors and split again instead, and not split at all if it only has one bucket.
     fn split(self, histogram: &Histogram) -> (VBox<'a>, Option<VBox<'a>>) {
-        match self.bounds.longest_dimension() {
-            Dimension::R => self
-                .colors
-                .sort_unstable_by(|a, b| [a.r, a.g, a.b].cmp(&[b.r, b.g, b.b])),
-            Dimension::G => self
-                .colors
-                .sort_unstable_by(|a, b| [a.g, a.r, a.b].cmp(&[b.g, b.r, b.b])),
-            Dimension::B => self
-                .colors
-                .sort_unstable_by(|a, b| [a.b, a.r, a.g].cmp(&[b.b, b.r, b.g])),
+        // A box of a single bucket can't be split, whatever its weight
+        if self.population == 0 || self.colors.len() == 1 {
+            return (self, None);
+        }
+
+        let dimension = self.bounds.longest_dimension();
+        let MinMax { min, max } = *self.bounds.0.get(dimension);
+        let (min, max) = (min.as_usize(), max.as_usize());
+
+        let mut partial_sums = vec![0_u64; max - min + 1];
+        for (color, count) in histogram.colors(self.colors) {
+            partial_sums[color.get(dimension).as_usize() - min] += count;
+        }
+        let mut total = 0;
+        for sum in &mut partial_sums {
+            total += *sum;
+            *sum = total;
+        }
+        // Cumulative population up to and including the position, 0 outside of the box
+        let partial_sum = |position: usize| {
+            position
+                .checked_sub(min)
+                .and_then(|i| partial_sums.get(i))
+                .copied()
+                .unwrap_or(0)
+        };
+
+        let median = (min..=max)
+            .find(|&position| partial_sum(position) as f64 > total as f64 / 2_f64)
+            .unwrap();
+        let left = median - min;
+        let right = max - median;
+        let mut cut = if left <= right {
+            (max.saturating_sub(1)).min(median + right / 2)
+        } else {
+            min.max(((median as f64 - 1_f64 - left as f64 / 2_f64) as isize).max(0) as usize)
+        };
+        // Avoid empty boxes
+        while partial_sum(cut) == 0 {
+            cut += 1;
+        }
+        while partial_sum(cut) == total && cut > 0 && partial_sum(cut - 1) != 0 {
+            cut -= 1;
         }
 
-        let split_point_population = self.population / 2;
-        // dbg!(self.population, split_point_population);
-        // Split after a sum of `split_point_population`, the first partition must not be empty and the last if possible neither
+        if partial_sum(cut) == total {
+            // All colors are in the last slice of the box, which is larger than its colors
+            let shrunk = VBox::from_colors(self.colors, histogram);
+            if shrunk.volume() == 1 {
+                return (shrunk, None);
+            }
+            return shrunk.split(histogram);
+        }
+
+        self.colors
+            .sort_unstable_by_key(|color| color.get(dimension).as_usize());
         let split_point = self
             .colors
-            .iter()
-            .position(|c| split_point_population <= histogram.count_of(c))
-            .map(|v| (v + 1))
-            .unwrap_or(self.colors.len())
-            .min(self.colors.len() - 1)
-            .max(1);
-        // dbg!(split_point, self.colors.len());
+            .partition_point(|color| color.get(dimension).as_usize() <= cut);
         let (a, b) = self.colors.split_at_mut(split_point);
-        let a = VBox::from_colors(a, histogram);
-        let b = Some(b)
-            .filter(|c| !c.is_empty())
-            .map(|c| VBox::from_colors(c, histogram));
-        (a, b)
+        let cut = Quantized::from_value_unchecked(cut, histogram.bits);
+        let mut bounds_a = self.bounds.clone();
+        bounds_a.0.get_mut(dimension).max = cut;
+        let mut bounds_b = self.bounds;
+        bounds_b.0.get_mut(dimension).min =
+            Quantized::from_value_unchecked(cut.as_usize() + 1, histogram.bits);
+        (
+            VBox::with_bounds(bounds_a, a, histogram),
+            Some(VBox::with_bounds(bounds_b, b, histogram)),
+        )
     }
 }
 
-trait Box: Ord + Sized {
-    fn split(self, histogram: &Histogram) -> (Self, Option<Self>);
+/// Score of a box, the box with the highest score is split next
+///
+/// Wide enough for weighted populations times volumes on 32-bit targets.
+type Score = fn(&VBox) -> u128;
+
+fn population(vbox: &VBox) -> u128 {
+    vbox.population as u128
 }
 
-trait Extractor {
-    fn extract(vbox: &VBox) -> usize;
+fn population_volume(vbox: &VBox) -> u128 {
+    vbox.population as u128 * vbox.volume() as u128
 }
 
-struct PopulationVolumeExtractor {}
+struct Scored<'a> {
+    score: u128,
+    vbox: VBox<'a>,
+}
 
-impl Extractor for PopulationVolumeExtractor {
-    fn extract(vbox: &VBox) -> usize {
-        vbox.population as usize * vbox.volume()
+impl PartialEq for Scored<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.score == other.score
     }
 }
 
-struct PopulationExtractor {}
+impl Eq for Scored<'_> {}
 
-impl Extractor for PopulationExtractor {
-    fn extract(vbox: &VBox) -> usize {
-        vbox.population as usize
+impl PartialOrd for Scored<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
     }
 }
 
-#[repr(transparent)]
-struct SortedVBox<'a, E> {
-    vbox: VBox<'a>,
-    _marker: PhantomData<E>,
-}
-
-impl<'a, E> SortedVBox<'a, E> {
-    fn new(vbox: VBox<'a>) -> Self {
-        Self {
-            vbox,
-            _marker: PhantomData,
-        }
+impl Ord for Scored<'_> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.score.cmp(&other.score)
     }
 }
 
-impl<'a, E: Extractor> SortedVBox<'a, E> {
-    fn extract(&self) -> usize {
-        E::extract(&self.vbox)
-    }
+/// Priority queue of boxes ordered by a score
+struct Queue<'a> {
+    heap: BinaryHeap<Scored<'a>>,
+    score: Score,
 }
 
-impl<'a, E: Extractor> PartialEq for SortedVBox<'a, E> {
-    fn eq(&self, other: &Self) -> bool {
-        self.extract() == other.extract()
+impl<'a> Queue<'a> {
+    fn new(score: Score) -> Self {
+        Self {
+            heap: BinaryHeap::new(),
+            score,
+        }
     }
-}
 
-impl<'a, E: Extractor> Eq for SortedVBox<'a, E> {}
+    fn push(&mut self, vbox: VBox<'a>) {
+        let score = (self.score)(&vbox);
+        self.heap.push(Scored { score, vbox });
+    }
 
-impl<'a, E: Extractor> PartialOrd for SortedVBox<'a, E> {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.cmp(other))
+    fn pop(&mut self) -> Option<VBox<'a>> {
+        self.heap.pop().map(|scored| scored.vbox)
     }
-}
 
-impl<'a, E: Extractor> Ord for SortedVBox<'a, E> {
-    fn cmp(&self, other: &Self) -> Ordering {
-        self.extract().cmp(&other.extract())
+    fn len(&self) -> usize {
+        self.heap.len()
+    }
+
+    /// Reorder the boxes by another score
+    fn rescore(self, score: Score) -> Self {
+        let heap = self
+            .heap
+            .into_vec()
+            .into_iter()
+            .map(|Scored { vbox, .. }| Scored {
+                score: score(&vbox),
+                vbox,
+            })
+            .collect();
+        Self { heap, score }
+    }
+
+    fn boxes(&self) -> impl Iterator<Item = &VBox<'a>> {
+        self.heap.iter().map(|scored| &scored.vbox)
+    }
+
+    /// Split the box with the highest score until there are `target` boxes
+    fn split(&mut self, histogram: &Histogram, target: usize) {
+        while self.len() < target {
+            let vbox = self.pop().unwrap();
+            let (vbox1, vbox2) = vbox.split(histogram);
+            self.push(vbox1);
+            if let Some(vbox2) = vbox2 {
+                self.push(vbox2);
+            } else {
+                // Split didn't happen
+                break;
+            }
+        }
     }
 }
 
-impl<'a, E: Extractor> Box for SortedVBox<'a, E> {
-    fn split(self, histogram: &Histogram) -> (Self, Option<Self>) {
-        let (a, b) = self.vbox.split(histogram);
-        (Self::new(a), b.map(Self::new))
-    }
+/// Median cut quantizer
+///
+/// Follows the modified median cut quantization (MMCQ) of color-thief and vibrant.js. First the
+/// boxes with the largest population are split, then the ones with the largest product of
+/// population and volume. Where MMCQ would create an empty box the colors are split again instead,
+/// so the palette has no color without pixels.
+#[derive(Debug)]
+pub struct MedianCut {
+    /// Fraction of the colors created by splitting the boxes with the largest population, from 0
+    /// to 1
+    pub population_fraction: f64,
+    /// Bits per channel of the histogram, from 1 to 8
+    ///
+    /// More bits separate similar colors, e.g. in gradients, but use more memory and time. 8 bits
+    /// take 128 MiB. With the `rayon` feature every thread counts into its own histogram of up to
+    /// 6 bits, i.e. 2 MiB, more bits are counted on one thread.
+    pub bits: u8,
 }
 
-fn split_boxes(queue: &mut BinaryHeap<impl Box>, histogram: &Histogram, target: usize) {
-    debug_assert_ne!(target, 0);
-    while queue.len() < target {
-        let vbox = queue.pop().unwrap();
-        let (vbox1, vbox2) = vbox.split(histogram);
-        queue.push(vbox1);
-        if let Some(vbox2) = vbox2 {
-            queue.push(vbox2);
-        } else {
-            // Split didn't happen
-            break;
+impl Default for MedianCut {
+    fn default() -> Self {
+        Self {
+            population_fraction: 0.75,
+            bits: 5,
         }
     }
 }
 
-/// Median cut quantizer
-#[derive(Debug, Default)]
-pub struct MedianCut;
-
 const COLOR_RANGE: Range<usize> = 2..257;
+const BITS_RANGE: Range<usize> = 1..9;
+/// Most bits of the histograms of the threads, more bits are counted on one thread
+#[cfg(feature = "rayon")]
+const MAX_PARALLEL_BITS: u8 = 6;
 
 impl Quantizer for MedianCut {
     fn quantize<I, P, F>(
         &self,
         image: &I,
         colors: usize,
-        quality: u32,
+        config: &PixelConfig,
         filter: F,
     ) -> Result<Vec<Color>, Error>
     where
         P: Pixel<Subpixel = u8> + 'static,
-        I: GenericImageView<Pixel = P>,
-        F: FnMut(&Rgba<u8>) -> bool,
+        I: GenericImageView<Pixel = P> + Sync,
+        F: Fn(&Rgba<u8>) -> bool + Sync,
     {
         if !COLOR_RANGE.contains(&colors) {
             return Err(Error::ColorCountOutOfBounds(colors, COLOR_RANGE));
         }
+        if !BITS_RANGE.contains(&(self.bits as usize)) {
+            return Err(Error::BitsOutOfBounds(self.bits, BITS_RANGE));
+        }
 
-        let image = {
-            let factor = 1.0 / quality as f64;
-            let width = (image.width() as f64 * factor).round() as u32;
-            let height = (image.height() as f64 * factor).round() as u32;
-            resize(image, width, height, FilterType::Lanczos3)
-        };
-        let (histogram, mut distinct_colors) =
-            Histogram::from_image(image.pixels().map(|p| p.to_rgba()), filter);
-        // let mut v = histogram
-        //     .buckets()
-        //     .filter(|(_, count)| count != &0)
-        //     .map(|(c, count)| (c.as_color_index(), count))
-        //     .collect::<Vec<_>>();
-        // v.sort_unstable_by_key(|&(_, c)| Reverse(c));
-        // dbg!(image.len());
-        // dbg!(v.len());
-        // for (c, count) in v {
-        //     println!("{}: {}", count, c);
-        // }
-
-        let vbox = VBox::from_colors(&mut distinct_colors, &histogram);
-        let mut queue = BinaryHeap::new();
-        queue.push(SortedVBox::<PopulationExtractor>::new(vbox));
-        split_boxes(&mut queue, &histogram, (0.75 * colors as f64) as usize);
-        let (slice, len, cap) = {
-            let mut me = ManuallyDrop::new(queue.into_vec());
-            (me.as_mut_ptr(), me.len(), me.capacity())
+        let pixels = config.pixels(image, filter)?;
+        let bits = self.bits as usize;
+        let serial = || {
+            let mut histogram = Histogram::new(bits);
+            pixels.for_each(|pixel, weight| histogram.insert(&pixel, weight));
+            histogram
         };
-        let vec = unsafe {
-            Vec::from_raw_parts(
-                slice as *mut SortedVBox<PopulationVolumeExtractor>,
-                len,
-                cap,
+        #[cfg(not(feature = "rayon"))]
+        let histogram = serial();
+        // Every thread has its own histogram, which is too large with more bits
+        #[cfg(feature = "rayon")]
+        let histogram = if self.bits > MAX_PARALLEL_BITS {
+            serial()
+        } else {
+            pixels.fold(
+                || Histogram::new(bits),
+                |histogram, pixel, weight| histogram.insert(&pixel, weight),
+                Histogram::merge,
             )
         };
-        let mut queue = BinaryHeap::from(vec);
-        split_boxes(&mut queue, &histogram, colors);
+        let mut distinct_colors = histogram.distinct_colors();
+        if distinct_colors.is_empty() {
+            return Err(Error::NoPixels);
+        }
+
+        let vbox = VBox::from_colors(&mut distinct_colors, &histogram);
+        let mut queue = Queue::new(population);
+        queue.push(vbox);
+        let fraction = self.population_fraction.clamp(0_f64, 1_f64);
+        // MMCQ stops once the number of boxes reaches the fraction, so round up
+        queue.split(&histogram, (fraction * colors as f64).ceil() as usize);
+        let mut queue = queue.rescore(population_volume);
+        queue.split(&histogram, colors);
+
+        Ok(queue.boxes().map(|b| b.average(&histogram)).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgb, RgbImage};
+
+    use super::*;
+    use crate::quantizer::testing::{quantize, xorshift};
 
-        Ok(queue.iter().map(|b| b.vbox.average(&histogram)).collect())
+    /// Image with xorshift noise of up to 20 on every channel
+    fn noisy(width: u32, height: u32, color: impl Fn(u32, u32) -> [i32; 3]) -> RgbImage {
+        let mut random = xorshift(0x9E37_79B9);
+        RgbImage::from_fn(width, height, |x, y| {
+            let noise = (random() % 41) as i32 - 20;
+            Rgb(color(x, y).map(|c| (c + noise).clamp(0, 255) as u8))
+        })
+    }
+
+    /// Red disc on a gradient
+    fn disc() -> RgbImage {
+        noisy(120, 80, |x, y| {
+            if (x as i32 - 60).pow(2) + (y as i32 - 40).pow(2) < 500 {
+                [200, 60, 40]
+            } else {
+                [(x * 2) as i32, (y * 3) as i32, 90]
+            }
+        })
+    }
+
+    /// Four colored quadrants
+    fn quadrants() -> RgbImage {
+        noisy(64, 64, |x, y| match (x / 32, y / 32) {
+            (0, 0) => [230, 200, 40],
+            (1, 0) => [30, 120, 200],
+            (0, _) => [40, 40, 50],
+            _ => [120, 200, 140],
+        })
+    }
+
+    /// Populations and colors sorted by population
+    fn palette(image: &RgbImage, colors: usize) -> Vec<(usize, [u8; 3])> {
+        let mut palette = quantize(&MedianCut::default(), image, colors)
+            .iter()
+            .map(|color| (color.population, color.color.0))
+            .collect::<Vec<_>>();
+        palette.sort_unstable();
+        palette
+    }
+
+    // The expected palettes are the output of the MMCQ of color-thief for the same pixels, in the
+    // variant where `iter` starts counting at `lh.size()` and the second phase is
+    // `iter(pq2, maxcolors)`. The original quantize.js of Nick Rabinowitz starts at 1 and calls
+    // `iter(pq2, maxcolors - pq2.size())`, so it returns fewer colors than requested.
+
+    #[test]
+    fn disc_matches_mmcq() {
+        assert_eq!(
+            palette(&disc(), 10),
+            [
+                (323, [198, 11, 86]),
+                (386, [145, 35, 87]),
+                (417, [226, 206, 96]),
+                (571, [36, 160, 88]),
+                (640, [37, 212, 91]),
+                (715, [199, 140, 90]),
+                (742, [95, 51, 92]),
+                (1446, [140, 207, 91]),
+                (1661, [34, 65, 87]),
+                (2699, [200, 63, 61]),
+            ]
+        );
+    }
+
+    #[test]
+    fn disc_matches_mmcq_with_rounded_up_fraction() {
+        // 0.75 * 22 = 16.5, so MMCQ splits by population until there are 17 boxes
+        assert_eq!(
+            palette(&disc(), 22),
+            [
+                (30, [173, 191, 68]),
+                (34, [186, 31, 74]),
+                (65, [187, 99, 74]),
+                (104, [82, 139, 100]),
+                (128, [185, 64, 74]),
+                (137, [241, 70, 100]),
+                (213, [116, 213, 91]),
+                (232, [220, 76, 61]),
+                (304, [35, 91, 88]),
+                (323, [198, 11, 86]),
+                (370, [32, 120, 88]),
+                (373, [87, 205, 92]),
+                (386, [145, 35, 87]),
+                (417, [226, 206, 96]),
+                (571, [36, 160, 88]),
+                (638, [97, 36, 91]),
+                (640, [37, 212, 91]),
+                (715, [199, 140, 90]),
+                (749, [196, 67, 95]),
+                (830, [169, 207, 92]),
+                (987, [34, 37, 86]),
+                (1354, [197, 58, 37]),
+            ]
+        );
+    }
+
+    #[test]
+    fn quadrants_match_mmcq() {
+        assert_eq!(
+            palette(&quadrants(), 10),
+            [
+                (77, [52, 140, 220]),
+                (97, [12, 100, 180]),
+                (101, [212, 180, 20]),
+                (102, [108, 188, 132]),
+                (138, [60, 60, 68]),
+                (219, [104, 184, 124]),
+                (703, [126, 206, 146]),
+                (850, [30, 120, 200]),
+                (886, [37, 37, 48]),
+                (923, [231, 202, 42]),
+            ]
+        );
+    }
+
+    #[test]
+    fn bits_are_bounded() {
+        for bits in [0, 9] {
+            let median_cut = MedianCut {
+                bits,
+                ..MedianCut::default()
+            };
+            let colors =
+                median_cut.quantize(&disc(), 8, &PixelConfig::default(), |_: &Rgba<u8>| true);
+            assert!(matches!(colors, Err(Error::BitsOutOfBounds(b, _)) if b == bits));
+        }
+    }
+
+    #[test]
+    fn populations_sum_to_pixels_for_all_bits() {
+        let image = disc();
+        for bits in 1..=8 {
+            let median_cut = MedianCut {
+                bits,
+                ..MedianCut::default()
+            };
+            let population = quantize(&median_cut, &image, 16)
+                .iter()
+                .map(|c| c.population)
+                .sum::<usize>();
+            assert_eq!(population, image.pixels().len(), "{} bits", bits);
+        }
+    }
+
+    #[test]
+    fn more_bits_separate_similar_colors() {
+        // Both colors fall into the same bin with 5 bits
+        let image = RgbImage::from_fn(20, 10, |x, _| {
+            if x < 10 {
+                Rgb([100, 100, 100])
+            } else {
+                Rgb([102, 101, 100])
+            }
+        });
+        let colors = |bits| {
+            let median_cut = MedianCut {
+                bits,
+                ..MedianCut::default()
+            };
+            let mut colors = quantize(&median_cut, &image, 4)
+                .iter()
+                .map(|c| (c.population, c.color.0))
+                .collect::<Vec<_>>();
+            colors.sort_unstable();
+            colors
+        };
+        assert_eq!(colors(5).len(), 1);
+        assert_eq!(colors(8), [(100, [100, 100, 100]), (100, [102, 101, 100])]);
     }
 }
@@ -1,42 +1,299 @@
-use image::{GenericImageView, Pixel, Rgb, Rgba};
+use image::{math::Rect, GenericImageView, GrayImage, Pixel, Rgb, Rgba, SubImage};
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
 
+use crate::saliency_map;
+
+mod kmeans;
 mod median_cut;
 mod neu;
+mod octree;
+mod region;
+mod sampling;
+#[cfg(test)]
+mod testing;
+mod weighting;
+mod wu;
 
+pub use kmeans::KMeans;
 pub use median_cut::MedianCut;
 pub use neu::Neu;
+pub use octree::{Octree, Reduction};
+pub use region::Region;
+pub use sampling::Sampling;
 use std::ops::Range;
+pub use weighting::Weighting;
+pub use wu::Wu;
 
 /// Color with population
 #[derive(Debug, Hash, PartialEq, Eq, Clone, Copy)]
 pub struct Color {
     /// Color
     pub color: Rgb<u8>,
-    /// Population
+    /// Population in pixels, weighted by the [`Weighting`]
+    ///
+    /// Colors of an image count at least one pixel, only generated colors have a population of 0,
+    /// see [`VibrancyConfig::generate_missing`](crate::VibrancyConfig::generate_missing).
     pub population: usize,
 }
 
 /// Errors when using a quantizer
 #[derive(Debug)]
 pub enum Error {
-    /// Quality was out of bounds
-    QualityOutOfBounds(u32, Range<usize>),
+    /// Sample factor of NeuQuant was out of bounds
+    SampleFactorOutOfBounds(u32, Range<usize>),
+    /// Sampling had a zero stride or pixel budget
+    InvalidSampling(Sampling),
     /// Color was out of bounds
     ColorCountOutOfBounds(usize, Range<usize>),
+    /// Depth of the tree was out of bounds
+    DepthOutOfBounds(u8, Range<usize>),
+    /// Bits per channel of the histogram were out of bounds
+    BitsOutOfBounds(u8, Range<usize>),
+    /// No pixel of the image passed the filter
+    NoPixels,
+}
+
+/// Pixels of an image used by a quantizer and how much they count
+///
+/// Bundles the [`Sampling`], [`Weighting`] and [`Region`], so they can be changed with
+/// `..Default::default()`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PixelConfig<'a> {
+    /// Pixels given to the quantizer
+    pub sampling: Sampling,
+    /// How much each pixel contributes to the population of its color
+    pub weighting: Weighting<'a>,
+    /// Part of the image to quantize
+    pub region: Region<'a>,
+}
+
+impl<'a> PixelConfig<'a> {
+    /// Use the sampling
+    pub fn with_sampling(mut self, sampling: Sampling) -> Self {
+        self.sampling = sampling;
+        self
+    }
+
+    /// Use the weighting
+    pub fn with_weighting(mut self, weighting: Weighting<'a>) -> Self {
+        self.weighting = weighting;
+        self
+    }
+
+    /// Use the region
+    pub fn with_region(mut self, region: Region<'a>) -> Self {
+        self.region = region;
+        self
+    }
+
+    /// Sampled pixels of the region of the image accepted by the filter
+    ///
+    /// Estimates the saliency map if needed. Fails with [`Error::InvalidSampling`] for a zero
+    /// stride or pixel budget.
+    fn pixels<'b, I, P, F>(&self, image: &'b I, filter: F) -> Result<Pixels<'b, I, F>, Error>
+    where
+        'a: 'b,
+        P: Pixel<Subpixel = u8> + 'static,
+        I: GenericImageView<Pixel = P>,
+        F: Fn(&Rgba<u8>) -> bool,
+    {
+        self.sampling.validate()?;
+        let saliency = match self.weighting {
+            Weighting::Saliency => Some(saliency_map(image)),
+            _ => None,
+        };
+        let dimensions = image.dimensions();
+        let (width, height) = dimensions;
+        let rect = self.region.bounds(dimensions).unwrap_or(Rect {
+            x: 0,
+            y: 0,
+            width,
+            height,
+        });
+        Ok(Pixels {
+            view: image.view(rect.x, rect.y, rect.width, rect.height),
+            offset: (rect.x, rect.y),
+            dimensions,
+            sampling: self.sampling,
+            weighting: self.weighting,
+            saliency,
+            region: self.region,
+            filter,
+        })
+    }
+}
+
+/// Sampled pixels of the region of an image accepted by a filter, visited by rows
+///
+/// The mask of the region is applied before sampling, so resampled pixels only average the pixels
+/// inside it.
+struct Pixels<'a, I, F> {
+    /// Rectangle of the region
+    view: SubImage<&'a I>,
+    /// Position of the view in the image
+    offset: (u32, u32),
+    /// Dimensions of the image
+    dimensions: (u32, u32),
+    sampling: Sampling,
+    weighting: Weighting<'a>,
+    /// Map replacing [`Weighting::Saliency`]
+    saliency: Option<GrayImage>,
+    region: Region<'a>,
+    filter: F,
+}
+
+impl<I, P, F> Pixels<'_, I, F>
+where
+    P: Pixel<Subpixel = u8> + 'static,
+    I: GenericImageView<Pixel = P>,
+    F: Fn(&Rgba<u8>) -> bool,
+{
+    /// Calls `f` with every pixel of the sampled row accepted by the filter and its weight,
+    /// skipping pixels without weight
+    fn for_each_in_row<G: FnMut(Rgba<u8>, u64)>(&self, row: u32, mut f: G) {
+        let weighting = match &self.saliency {
+            Some(saliency) => Weighting::Map(saliency),
+            None => self.weighting,
+        };
+        let (left, top) = self.offset;
+        self.sampling.for_each_in_row(
+            &*self.view,
+            row,
+            |x, y| self.region.accepts(left + x, top + y, self.dimensions),
+            |x, y, pixel| {
+                if (self.filter)(&pixel) {
+                    let weight = weighting.weight(left + x, top + y, self.dimensions, &pixel);
+                    if weight != 0 {
+                        f(pixel, weight);
+                    }
+                }
+            },
+        );
+    }
+
+    /// Calls `f` with every pixel accepted by the filter and its weight in row-major order,
+    /// skipping pixels without weight
+    fn for_each<G: FnMut(Rgba<u8>, u64)>(&self, mut f: G) {
+        for row in 0..self.sampling.rows(self.view.dimensions()) {
+            self.for_each_in_row(row, &mut f);
+        }
+    }
+
+    /// Folds the pixels accepted by the filter and their weights into one accumulator per thread
+    /// over consecutive rows, then reduces the accumulators in row order
+    ///
+    /// Sampling, filtering and weighting run in parallel, and no more accumulators than threads
+    /// are created, e.g. for large histograms.
+    #[cfg(feature = "rayon")]
+    fn fold<T, A, G, R>(&self, init: A, fold: G, reduce: R) -> T
+    where
+        I: Sync,
+        F: Sync,
+        T: Send,
+        A: Fn() -> T + Sync + Send,
+        G: Fn(&mut T, Rgba<u8>, u64) + Sync + Send,
+        R: Fn(T, T) -> T + Sync + Send,
+    {
+        let rows = self.sampling.rows(self.view.dimensions());
+        let rows_per_thread = (rows as usize)
+            .div_ceil(rayon::current_num_threads())
+            .max(1);
+        (0..rows)
+            .into_par_iter()
+            .with_min_len(rows_per_thread)
+            .fold(&init, |mut accumulator, row| {
+                self.for_each_in_row(row, |pixel, weight| fold(&mut accumulator, pixel, weight));
+                accumulator
+            })
+            .reduce_with(reduce)
+            .unwrap_or_else(init)
+    }
 }
 
 /// Quantizer trait
 pub trait Quantizer {
-    /// Quantizes the input image into the given color count using all pixels for which filter returns true
+    /// Quantizes the input image into the given color count using the pixels selected by the
+    /// config for which filter returns true
+    ///
+    /// Returns [`Error::NoPixels`] if the filter rejects every pixel. The image and the filter are
+    /// shared between threads with the `rayon` feature.
     fn quantize<I, P, F>(
         &self,
         image: &I,
         colors: usize,
-        quality: u32,
+        config: &PixelConfig,
         filter: F,
     ) -> Result<Vec<Color>, Error>
     where
         P: Pixel<Subpixel = u8> + 'static,
-        I: GenericImageView<Pixel = P>,
-        F: FnMut(&Rgba<u8>) -> bool;
+        I: GenericImageView<Pixel = P> + Sync,
+        F: Fn(&Rgba<u8>) -> bool + Sync;
+}
+
+#[cfg(all(test, feature = "rayon"))]
+mod tests {
+    use image::{GrayImage, Luma, RgbImage};
+
+    use super::*;
+
+    #[test]
+    fn parallel_pixels_match_serial() {
+        let image = RgbImage::from_fn(301, 203, |x, y| {
+            Rgb([(x * 7 + y) as u8, (y * 5) as u8, (x ^ y) as u8])
+        });
+        let mask = GrayImage::from_fn(10, 10, |x, y| Luma([if x > y { 255 } else { 0 }]));
+        let region = Region::default().with_rect(Rect {
+            x: 13,
+            y: 7,
+            width: 250,
+            height: 180,
+        });
+        let samplings = [
+            Sampling::Full,
+            Sampling::Stride(7),
+            Sampling::Area { max_pixels: 2000 },
+            Sampling::Nearest { max_pixels: 2000 },
+        ];
+        let weightings = [Weighting::Uniform, Weighting::Center, Weighting::Saliency];
+        let stripes = |x: u32, _| x % 3 != 0;
+        let regions = [
+            Region::default(),
+            region,
+            region.with_mask(&mask),
+            region.with_predicate(&stripes),
+        ];
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(4)
+            .build()
+            .unwrap();
+        for sampling in samplings {
+            for weighting in weightings {
+                for region in regions {
+                    let config = PixelConfig {
+                        sampling,
+                        weighting,
+                        region,
+                    };
+                    let pixels = config
+                        .pixels(&image, |pixel: &Rgba<u8>| pixel.0[0] > 20)
+                        .unwrap();
+                    let mut serial = Vec::new();
+                    pixels.for_each(|pixel, weight| serial.push((pixel, weight)));
+                    let parallel = pool.install(|| {
+                        pixels.fold(
+                            Vec::new,
+                            |pixels, pixel, weight| pixels.push((pixel, weight)),
+                            |mut pixels, other| {
+                                pixels.extend(other);
+                                pixels
+                            },
+                        )
+                    });
+                    assert!(!serial.is_empty());
+                    assert_eq!(serial, parallel, "{:?}", config);
+                }
+            }
+        }
+    }
 }
@@ -1,51 +1,138 @@
-use crate::{Color, Error, Quantizer};
+use super::weighting::{self, FULL_WEIGHT};
+use crate::{Color, Error, PixelConfig, Quantizer};
 use color_quant::NeuQuant;
 use image::{GenericImageView, Pixel, Rgb, Rgba};
 use itertools::Itertools;
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::ops::Range;
 
 /// Neuronal network based quantizer
-#[derive(Debug, Default)]
-pub struct Neu;
+///
+/// The network learns from the pixels in proportion to their weight, pixels with less than full
+/// weight are left out of the training accordingly.
+#[derive(Debug)]
+pub struct Neu {
+    /// Sample factor of NeuQuant from 1 to 30, higher is faster but worse
+    ///
+    /// The network only learns from every n-th of the pixels chosen by the
+    /// [`Sampling`](crate::Sampling), so both reduce the pixels. The default of 1 leaves the
+    /// sampling as the only one; the counts of the colors always use every sampled pixel.
+    pub sample_factor: u32,
+}
+
+impl Default for Neu {
+    fn default() -> Self {
+        Self { sample_factor: 1 }
+    }
+}
+
+const SAMPLE_FACTOR_RANGE: Range<usize> = 1..31;
+
+/// Pixels to train the network with, each repeated by its weight in full weights
+///
+/// The fractions are carried over to the following pixels, so the training keeps the weighted
+/// distribution of the colors. Falls back to all pixels if their total weight is below one pixel.
+fn training_pixels<'a>(flat_pixels: &'a [u8], weights: &[u64]) -> Cow<'a, [u8]> {
+    if weights.iter().all(|&weight| weight == FULL_WEIGHT) {
+        return Cow::Borrowed(flat_pixels);
+    }
+    let mut training = Vec::with_capacity(flat_pixels.len());
+    let mut carry = 0;
+    for (rgba, &weight) in flat_pixels.chunks_exact(4).zip(weights) {
+        carry += weight;
+        while carry >= FULL_WEIGHT {
+            training.extend_from_slice(rgba);
+            carry -= FULL_WEIGHT;
+        }
+    }
+    if training.is_empty() {
+        return Cow::Borrowed(flat_pixels);
+    }
+    Cow::Owned(training)
+}
 
 impl Quantizer for Neu {
     fn quantize<I, P, F>(
         &self,
         image: &I,
         colors: usize,
-        quality: u32,
+        config: &PixelConfig,
         filter: F,
     ) -> Result<Vec<Color>, Error>
     where
         P: Pixel<Subpixel = u8> + 'static,
-        I: GenericImageView<Pixel = P>,
-        F: FnMut(&Rgba<u8>) -> bool,
+        I: GenericImageView<Pixel = P> + Sync,
+        F: Fn(&Rgba<u8>) -> bool + Sync,
     {
-        if !(1..31).contains(&quality) {
-            return Err(Error::QualityOutOfBounds(quality, 1..31));
+        if !SAMPLE_FACTOR_RANGE.contains(&(self.sample_factor as usize)) {
+            return Err(Error::SampleFactorOutOfBounds(
+                self.sample_factor,
+                SAMPLE_FACTOR_RANGE,
+            ));
         }
         if !(64..266).contains(&colors) {
             return Err(Error::ColorCountOutOfBounds(colors, 64..266));
         }
 
-        let pixels = image
-            .pixels()
-            .map(|(_, _, pixel)| pixel.to_rgba())
-            .filter(filter);
+        let pixels = config.pixels(image, filter)?;
+        #[cfg(not(feature = "rayon"))]
+        let (flat_pixels, weights) = {
+            let mut flat_pixels: Vec<u8> = Vec::new();
+            let mut weights = Vec::new();
+            pixels.for_each(|rgba, weight| {
+                flat_pixels.extend_from_slice(&rgba.0);
+                weights.push(weight);
+            });
+            (flat_pixels, weights)
+        };
+        // NeuQuant learns from the pixels in order, so they are concatenated in row order
+        #[cfg(feature = "rayon")]
+        let (flat_pixels, weights) = pixels.fold(
+            || (Vec::<u8>::new(), Vec::new()),
+            |(flat_pixels, weights), rgba, weight| {
+                flat_pixels.extend_from_slice(&rgba.0);
+                weights.push(weight);
+            },
+            |(mut flat_pixels, mut weights), (other_pixels, other_weights)| {
+                flat_pixels.extend(other_pixels);
+                weights.extend(other_weights);
+                (flat_pixels, weights)
+            },
+        );
 
-        let mut flat_pixels: Vec<u8> =
-            Vec::with_capacity(4 * image.height() as usize * image.width() as usize);
-        for rgba in pixels {
-            for subpixel in &rgba.0 {
-                flat_pixels.push(*subpixel);
-            }
+        if flat_pixels.is_empty() {
+            return Err(Error::NoPixels);
         }
 
-        let quantize = NeuQuant::new(quality as i32, colors, &flat_pixels);
+        let training = training_pixels(&flat_pixels, &weights);
+        let quantize = NeuQuant::new(self.sample_factor as i32, colors, &training);
 
-        let pixel_counts = flat_pixels
-            .chunks_exact(4)
-            .map(|rgba| quantize.index_of(rgba))
-            .counts();
+        let count = |mut counts: HashMap<usize, u64>, (pixels, weights): (&[u8], &[u64])| {
+            for (rgba, weight) in pixels.chunks_exact(4).zip(weights) {
+                *counts.entry(quantize.index_of(rgba)).or_insert(0) += weight;
+            }
+            counts
+        };
+        #[cfg(not(feature = "rayon"))]
+        let pixel_counts = count(HashMap::new(), (&flat_pixels, &weights));
+        #[cfg(feature = "rayon")]
+        let pixel_counts = {
+            // One chunk of pixels per thread
+            let chunk = weights.len().div_ceil(rayon::current_num_threads());
+            flat_pixels
+                .par_chunks(4 * chunk)
+                .zip(weights.par_chunks(chunk))
+                .fold(HashMap::new, count)
+                .reduce(HashMap::new, |mut counts, other| {
+                    for (index, count) in other {
+                        *counts.entry(index).or_insert(0) += count;
+                    }
+                    counts
+                })
+        };
 
         let palette = quantize
             .color_map_rgb()
@@ -54,10 +141,29 @@ impl Quantizer for Neu {
             .flat_map(|(i, rgb)| pixel_counts.get(&i).map(|&count| (count, rgb)))
             .map(|(count, rgb)| Color {
                 color: *Rgb::from_slice(rgb),
-                population: count,
+                population: weighting::population(count),
             })
             .unique_by(|c| c.color)
             .collect();
         Ok(palette)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn training_follows_weights() {
+        let pixels = [1, 1, 1, 255, 2, 2, 2, 255, 3, 3, 3, 255];
+        assert_eq!(
+            training_pixels(&pixels, &[FULL_WEIGHT; 3]),
+            Cow::Borrowed(&pixels[..])
+        );
+        assert_eq!(
+            &*training_pixels(&pixels, &[128, 128, FULL_WEIGHT]),
+            &[2, 2, 2, 255, 3, 3, 3, 255]
+        );
+        assert_eq!(&*training_pixels(&pixels, &[10, 10, 10]), &pixels[..]);
+    }
+}
@@ -0,0 +1,304 @@
+use std::ops::Range;
+
+use image::{GenericImageView, Pixel, Rgb, Rgba};
+
+use super::weighting;
+use crate::{Color, Error, PixelConfig, Quantizer};
+
+/// Strategy choosing the node whose children are merged when the tree has too many leaves
+///
+/// Only nodes of the deepest level with children are candidates.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub enum Reduction {
+    /// Merge the most recently created node, the fastest choice
+    #[default]
+    Newest,
+    /// Merge the node with the smallest number of pixels, so large clusters keep their detail
+    LeastPopulation,
+}
+
+/// Octree quantizer
+///
+/// Colors are inserted into a tree which branches on one bit per channel at each level. Whenever
+/// there are more leaves than colors requested, the children of a node are merged, which bounds
+/// the memory used. The eight octants below the root are never merged, if more of them than colors
+/// remain, the least populated ones are merged into the nearest ones at the end.
+#[derive(Debug, Clone)]
+pub struct Octree {
+    /// Depth of the leaves, i.e. the number of bits per channel, from 1 to 8
+    pub max_depth: u8,
+    /// Which nodes to merge first
+    pub reduction: Reduction,
+}
+
+impl Default for Octree {
+    fn default() -> Self {
+        Self {
+            max_depth: 8,
+            reduction: Reduction::Newest,
+        }
+    }
+}
+
+/// Index of nodes in the tree, the root is never a child so 0 marks a missing child
+type NodeId = u32;
+
+const ROOT: NodeId = 0;
+
+#[derive(Debug, Default, Clone)]
+struct Node {
+    children: [NodeId; 8],
+    leaf: bool,
+    /// Weight of the pixels in this subtree
+    population: u64,
+    /// Weighted sum of the colors of the pixels stored in this leaf
+    sum: [u64; 3],
+}
+
+struct Tree {
+    nodes: Vec<Node>,
+    free: Vec<NodeId>,
+    /// Nodes with children per level
+    reducible: Vec<Vec<NodeId>>,
+    leaves: usize,
+    max_depth: usize,
+    reduction: Reduction,
+}
+
+impl Tree {
+    fn new(max_depth: usize, reduction: Reduction) -> Self {
+        Self {
+            nodes: vec![Node::default()],
+            free: Vec::new(),
+            // The root is never merged, so the octants of the first level stay apart
+            reducible: vec![Vec::new(); max_depth],
+            leaves: 0,
+            max_depth,
+            reduction,
+        }
+    }
+
+    fn allocate(&mut self, leaf: bool) -> NodeId {
+        let node = Node {
+            leaf,
+            ..Node::default()
+        };
+        match self.free.pop() {
+            Some(id) => {
+                self.nodes[id as usize] = node;
+                id
+            }
+            None => {
+                self.nodes.push(node);
+                (self.nodes.len() - 1) as NodeId
+            }
+        }
+    }
+
+    fn insert(&mut self, color: [u8; 3], weight: u64) {
+        let mut id = ROOT;
+        let mut level = 0;
+        loop {
+            let node = &mut self.nodes[id as usize];
+            node.population += weight;
+            if node.leaf {
+                for (sum, &c) in node.sum.iter_mut().zip(&color) {
+                    *sum += c as u64 * weight;
+                }
+                return;
+            }
+
+            let shift = 7 - level;
+            let [r, g, b] = color.map(|c| ((c >> shift) & 1) as usize);
+            let index = (r << 2) | (g << 1) | b;
+            let child = node.children[index];
+            id = if child != ROOT {
+                child
+            } else {
+                let leaf = level + 1 == self.max_depth;
+                let child = self.allocate(leaf);
+                if leaf {
+                    self.leaves += 1;
+                } else {
+                    self.reducible[level + 1].push(child);
+                }
+                self.nodes[id as usize].children[index] = child;
+                child
+            };
+            level += 1;
+        }
+    }
+
+    /// Merge the children of one node of the deepest level, returns false if only the children of
+    /// the root are left
+    fn reduce(&mut self) -> bool {
+        let level = match self.reducible.iter().rposition(|nodes| !nodes.is_empty()) {
+            Some(level) => level,
+            None => return false,
+        };
+        let nodes = &mut self.reducible[level];
+        let position = match self.reduction {
+            Reduction::Newest => nodes.len() - 1,
+            Reduction::LeastPopulation => {
+                let nodes_ref = &self.nodes;
+                nodes
+                    .iter()
+                    .enumerate()
+                    .min_by_key(|&(_, &id)| nodes_ref[id as usize].population)
+                    .unwrap()
+                    .0
+            }
+        };
+        let id = nodes.swap_remove(position);
+
+        let children = std::mem::take(&mut self.nodes[id as usize].children);
+        let mut sum = [0_u64; 3];
+        for &child in children.iter().filter(|&&child| child != ROOT) {
+            for (sum, c) in sum.iter_mut().zip(&self.nodes[child as usize].sum) {
+                *sum += c;
+            }
+            self.leaves -= 1;
+            self.free.push(child);
+        }
+        let node = &mut self.nodes[id as usize];
+        node.leaf = true;
+        node.sum = sum;
+        self.leaves += 1;
+        true
+    }
+
+    /// Average colors of the leaves, merging the least populated leaf into the leaf with the
+    /// nearest average until there are at most `count` left
+    fn colors(&self, count: usize) -> Vec<Color> {
+        let mut leaves = Vec::with_capacity(self.leaves);
+        let mut stack = vec![ROOT];
+        while let Some(id) = stack.pop() {
+            let node = &self.nodes[id as usize];
+            if node.leaf {
+                if node.population != 0 {
+                    leaves.push((node.population, node.sum));
+                }
+            } else {
+                stack.extend(node.children.iter().filter(|&&child| child != ROOT));
+            }
+        }
+
+        let mean =
+            |&(population, sum): &(u64, [u64; 3])| sum.map(|sum| sum as f64 / population as f64);
+        while leaves.len() > count.max(1) {
+            let (smallest, _) = leaves
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, &(population, _))| population)
+                .unwrap();
+            let (population, sum) = leaves.swap_remove(smallest);
+            let color = mean(&(population, sum));
+            let distance = |leaf: &(u64, [u64; 3])| {
+                mean(leaf)
+                    .iter()
+                    .zip(&color)
+                    .map(|(a, b)| (a - b).powi(2))
+                    .sum::<f64>()
+            };
+            let nearest = leaves
+                .iter_mut()
+                .min_by(|a, b| distance(a).total_cmp(&distance(b)))
+                .unwrap();
+            nearest.0 += population;
+            for (sum, c) in nearest.1.iter_mut().zip(sum) {
+                *sum += c;
+            }
+        }
+
+        leaves
+            .iter()
+            .map(|&(population, sum)| Color {
+                color: Rgb(sum.map(|sum| (sum as f64 / population as f64).round() as u8)),
+                population: weighting::population(population),
+            })
+            .collect()
+    }
+}
+
+const COLOR_RANGE: Range<usize> = 2..257;
+const DEPTH_RANGE: Range<usize> = 1..9;
+
+impl Quantizer for Octree {
+    fn quantize<I, P, F>(
+        &self,
+        image: &I,
+        colors: usize,
+        config: &PixelConfig,
+        filter: F,
+    ) -> Result<Vec<Color>, Error>
+    where
+        P: Pixel<Subpixel = u8> + 'static,
+        I: GenericImageView<Pixel = P> + Sync,
+        F: Fn(&Rgba<u8>) -> bool + Sync,
+    {
+        if !COLOR_RANGE.contains(&colors) {
+            return Err(Error::ColorCountOutOfBounds(colors, COLOR_RANGE));
+        }
+        if !DEPTH_RANGE.contains(&(self.max_depth as usize)) {
+            return Err(Error::DepthOutOfBounds(self.max_depth, DEPTH_RANGE));
+        }
+
+        let mut tree = Tree::new(self.max_depth as usize, self.reduction);
+        config.pixels(image, filter)?.for_each(|pixel, weight| {
+            let [r, g, b, _] = pixel.0;
+            tree.insert([r, g, b], weight);
+            while tree.leaves > colors && tree.reduce() {}
+        });
+        if tree.nodes[ROOT as usize].population == 0 {
+            return Err(Error::NoPixels);
+        }
+
+        Ok(tree.colors(colors))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::RgbImage;
+
+    use super::*;
+    use crate::quantizer::testing::quantize;
+
+    /// One flat color in each octant of the first level
+    fn octants() -> RgbImage {
+        RgbImage::from_fn(80, 10, |x, _| {
+            let octant = x / 10;
+            let channel = |bit: u32| if octant & bit != 0 { 200 } else { 50 };
+            Rgb([channel(4), channel(2), channel(1)])
+        })
+    }
+
+    #[test]
+    fn colors_are_bounded_without_collapsing() {
+        for reduction in [Reduction::Newest, Reduction::LeastPopulation] {
+            let octree = Octree {
+                reduction,
+                ..Octree::default()
+            };
+            for colors in [2, 4, 7, 8, 16] {
+                let palette = quantize(&octree, &octants(), colors);
+                assert!(palette.len() <= colors);
+                assert_eq!(palette.len(), colors.min(8));
+            }
+        }
+    }
+
+    #[test]
+    fn populations_sum_to_pixels() {
+        let gradient = RgbImage::from_fn(64, 48, |x, y| Rgb([(x * 4) as u8, (y * 5) as u8, 128]));
+        for image in [octants(), gradient] {
+            for colors in [2, 7, 64] {
+                let population = quantize(&Octree::default(), &image, colors)
+                    .iter()
+                    .map(|c| c.population)
+                    .sum::<usize>();
+                assert_eq!(population, image.pixels().len());
+            }
+        }
+    }
+}
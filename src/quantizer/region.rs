@@ -0,0 +1,172 @@
+use std::fmt;
+
+use image::{math::Rect, GrayImage};
+
+use super::weighting::stretched;
+
+/// Part of an image to quantize, like `setRegion` of the Android palette
+///
+/// Positions refer to the whole image, so the weighting, the mask and the predicate are not
+/// cropped with the rectangle.
+#[derive(Clone, Copy, Default)]
+pub struct Region<'a> {
+    /// Rectangle of the image, clamped to its bounds; `None` for the whole image
+    pub rect: Option<Rect>,
+    /// Mask where only pixels with a non-zero value are used; `None` for every pixel
+    ///
+    /// The mask is stretched to the size of the image.
+    pub mask: Option<&'a GrayImage>,
+    /// Positions of the pixels to use; `None` for every pixel
+    ///
+    /// Unlike a mask it needs no memory, e.g. for shapes computed from the position.
+    pub predicate: Option<&'a (dyn Fn(u32, u32) -> bool + Sync)>,
+}
+
+impl fmt::Debug for Region<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Region")
+            .field("rect", &self.rect)
+            .field("mask", &self.mask)
+            .field("predicate", &self.predicate.map(|_| "Fn(u32, u32) -> bool"))
+            .finish()
+    }
+}
+
+impl<'a> Region<'a> {
+    /// Only use the pixels in the rectangle
+    pub fn with_rect(mut self, rect: Rect) -> Self {
+        self.rect = Some(rect);
+        self
+    }
+
+    /// Only use the pixels where the mask is not zero
+    pub fn with_mask(mut self, mask: &'a GrayImage) -> Self {
+        self.mask = Some(mask);
+        self
+    }
+
+    /// Only use the pixels at the positions accepted by the predicate
+    pub fn with_predicate(mut self, predicate: &'a (dyn Fn(u32, u32) -> bool + Sync)) -> Self {
+        self.predicate = Some(predicate);
+        self
+    }
+
+    /// Rectangle clamped to an image of the given dimensions, `None` for the whole image
+    pub(crate) fn bounds(&self, (width, height): (u32, u32)) -> Option<Rect> {
+        let rect = self.rect?;
+        let x = rect.x.min(width);
+        let y = rect.y.min(height);
+        let bounds = Rect {
+            x,
+            y,
+            width: rect.width.min(width - x),
+            height: rect.height.min(height - y),
+        };
+        if bounds
+            == (Rect {
+                x: 0,
+                y: 0,
+                width,
+                height,
+            })
+        {
+            return None;
+        }
+        Some(bounds)
+    }
+
+    /// Whether the mask and the predicate accept the position in an image of the given
+    /// dimensions
+    pub(crate) fn accepts(&self, x: u32, y: u32, dimensions: (u32, u32)) -> bool {
+        self.mask
+            .map_or(true, |mask| stretched(mask, x, y, dimensions) != 0)
+            && self.predicate.map_or(true, |predicate| predicate(x, y))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Luma, Rgb, RgbImage, Rgba};
+
+    use super::*;
+    use crate::{MedianCut, PixelConfig, Quantizer, Sampling};
+
+    // Centers of the bins of the histogram of MedianCut
+    const COLORS: [[u8; 3]; 4] = [[204, 44, 44], [44, 204, 44], [44, 44, 204], [204, 204, 44]];
+
+    /// Quadrants of the colors in row-major order
+    fn quadrants() -> RgbImage {
+        RgbImage::from_fn(40, 40, |x, y| Rgb(COLORS[(y / 20 * 2 + x / 20) as usize]))
+    }
+
+    fn palette(region: Region, sampling: Sampling) -> Vec<(usize, [u8; 3])> {
+        let config = PixelConfig::default()
+            .with_sampling(sampling)
+            .with_region(region);
+        let mut palette = MedianCut::default()
+            .quantize(&quadrants(), 8, &config, |_: &Rgba<u8>| true)
+            .unwrap()
+            .iter()
+            .map(|color| (color.population, color.color.0))
+            .collect::<Vec<_>>();
+        palette.sort_unstable();
+        palette
+    }
+
+    #[test]
+    fn rect_only_yields_colors_inside() {
+        let region = Region::default().with_rect(Rect {
+            x: 20,
+            y: 0,
+            width: 20,
+            height: 20,
+        });
+        assert_eq!(palette(region, Sampling::Full), [(400, COLORS[1])]);
+        // Resampling stays inside the rectangle as well
+        let sampling = Sampling::Area { max_pixels: 25 };
+        assert_eq!(palette(region, sampling), [(25, COLORS[1])]);
+
+        let region = Region::default().with_rect(Rect {
+            x: 10,
+            y: 30,
+            width: 20,
+            height: 10,
+        });
+        assert_eq!(
+            palette(region, Sampling::Full),
+            [(100, COLORS[2]), (100, COLORS[3])]
+        );
+    }
+
+    #[test]
+    fn mask_only_yields_colors_inside() {
+        let mask = GrayImage::from_fn(2, 2, |x, y| Luma([if x == 0 && y == 1 { 1 } else { 0 }]));
+        let region = Region::default().with_mask(&mask);
+        assert_eq!(palette(region, Sampling::Full), [(400, COLORS[2])]);
+        let sampling = Sampling::Area { max_pixels: 100 };
+        assert_eq!(palette(region, sampling), [(25, COLORS[2])]);
+    }
+
+    #[test]
+    fn predicate_only_yields_colors_inside() {
+        let lower_right = |x, y| x >= 20 && y >= 20;
+        let region = Region::default().with_predicate(&lower_right);
+        assert_eq!(palette(region, Sampling::Full), [(400, COLORS[3])]);
+    }
+
+    #[test]
+    fn rect_is_clamped_to_image() {
+        let rect = |x, y, width, height| Rect {
+            x,
+            y,
+            width,
+            height,
+        };
+        let bounds = |r| Region::default().with_rect(r).bounds((40, 30));
+        assert_eq!(bounds(rect(30, 20, 100, 100)), Some(rect(30, 20, 10, 10)));
+        assert_eq!(bounds(rect(50, 50, 10, 10)), Some(rect(40, 30, 0, 0)));
+        assert_eq!(bounds(rect(0, 0, 40, 30)), None);
+        assert_eq!(bounds(rect(0, 0, 100, 100)), None);
+        assert_eq!(Region::default().bounds((40, 30)), None);
+    }
+}
@@ -0,0 +1,276 @@
+use image::{GenericImageView, Pixel, Rgba};
+
+use crate::Error;
+
+/// Pixels of an image given to a quantizer
+///
+/// The same sampling works with every quantizer, so the same image yields comparable palettes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sampling {
+    /// Every pixel
+    Full,
+    /// Every n-th pixel in row-major order, like the `quality` of color-thief
+    Stride(u32),
+    /// Average areas of pixels to at most `max_pixels` pixels, like `resizeArea` of Android
+    ///
+    /// Pixels outside the mask of the [`Region`](crate::Region) are left out of the averages.
+    /// Smaller images are used fully.
+    Area {
+        /// Maximum number of pixels
+        max_pixels: u32,
+    },
+    /// Pick the pixels nearest to a grid of at most `max_pixels` pixels
+    ///
+    /// Smaller images are used fully.
+    Nearest {
+        /// Maximum number of pixels
+        max_pixels: u32,
+    },
+}
+
+impl Default for Sampling {
+    /// Area sampling to 112 by 112 pixels like the Android palette
+    fn default() -> Self {
+        Sampling::Area {
+            max_pixels: 112 * 112,
+        }
+    }
+}
+
+impl Sampling {
+    /// Dimensions of the sampled grid, `None` if every pixel is used
+    fn scaled(&self, width: u32, height: u32) -> Option<(u32, u32)> {
+        let max_pixels = match *self {
+            Sampling::Area { max_pixels } | Sampling::Nearest { max_pixels } => max_pixels,
+            Sampling::Full | Sampling::Stride(_) => return None,
+        };
+        let area = width as u64 * height as u64;
+        if area <= max_pixels as u64 {
+            return None;
+        }
+        let ratio = (max_pixels as f64 / area as f64).sqrt();
+        let scale = |side: u32| ((side as f64 * ratio) as u32).max(1);
+        let (width, height) = (scale(width), scale(height));
+        // A short side rounded up to one pixel leaves less of the budget to the long side
+        if width >= height {
+            Some((width.min(max_pixels / height), height))
+        } else {
+            Some((width, height.min(max_pixels / width)))
+        }
+    }
+
+    /// Fails with [`Error::InvalidSampling`] for a zero stride or pixel budget
+    pub(crate) fn validate(&self) -> Result<(), Error> {
+        match *self {
+            Sampling::Stride(0)
+            | Sampling::Area { max_pixels: 0 }
+            | Sampling::Nearest { max_pixels: 0 } => Err(Error::InvalidSampling(*self)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Number of sampled rows of an image of the given dimensions
+    pub(crate) fn rows(&self, (width, height): (u32, u32)) -> u32 {
+        if width == 0 || height == 0 {
+            return 0;
+        }
+        self.scaled(width, height)
+            .map_or(height, |(_, scaled_height)| scaled_height)
+    }
+
+    /// Calls `f` with the position in the image and the color of every sampled pixel of the row
+    /// accepted by `accepts`
+    ///
+    /// Rows are independent, so they can be sampled in any order or in parallel. Resampled pixels
+    /// are positioned at the image pixel under their center. Area sampling only averages the
+    /// accepted pixels and skips areas without any. The sampling has to be valid and the row less
+    /// than [`Sampling::rows`].
+    pub(crate) fn for_each_in_row<I, P, A, F>(&self, image: &I, row: u32, accepts: A, mut f: F)
+    where
+        P: Pixel<Subpixel = u8> + 'static,
+        I: GenericImageView<Pixel = P>,
+        A: Fn(u32, u32) -> bool,
+        F: FnMut(u32, u32, Rgba<u8>),
+    {
+        let (width, height) = image.dimensions();
+        let (scaled_width, scaled_height) = match self.scaled(width, height) {
+            Some(dimensions) => dimensions,
+            None => {
+                let step = match *self {
+                    Sampling::Stride(step) => step as u64,
+                    _ => 1,
+                };
+                // Continue the stride of the previous rows in row-major order
+                let first = (step - row as u64 * width as u64 % step) % step;
+                for x in (first..width as u64).step_by(step as usize) {
+                    let x = x as u32;
+                    if accepts(x, row) {
+                        f(x, row, image.get_pixel(x, row).to_rgba());
+                    }
+                }
+                return;
+            }
+        };
+
+        let source = |x: u32, scaled: u32, side: u32| {
+            (((x as f64 + 0.5) * side as f64 / scaled as f64) as u32).min(side - 1)
+        };
+        // Pixels of the image covered by a resampled pixel
+        let span = |x: u32, scaled: u32, side: u32| {
+            let bound = |x: u32| (x as u64 * side as u64 / scaled as u64) as u32;
+            bound(x)..bound(x + 1)
+        };
+        let source_y = source(row, scaled_height, height);
+        for x in 0..scaled_width {
+            let source_x = source(x, scaled_width, width);
+            match *self {
+                Sampling::Area { .. } => {
+                    let mut sum = [0_u64; 4];
+                    let mut count = 0_u64;
+                    for y in span(row, scaled_height, height) {
+                        for x in span(x, scaled_width, width) {
+                            if accepts(x, y) {
+                                let pixel = image.get_pixel(x, y).to_rgba();
+                                for (sum, channel) in sum.iter_mut().zip(pixel.0) {
+                                    *sum += channel as u64;
+                                }
+                                count += 1;
+                            }
+                        }
+                    }
+                    if count != 0 {
+                        let average = sum.map(|sum| ((sum + count / 2) / count) as u8);
+                        f(source_x, source_y, Rgba(average));
+                    }
+                }
+                _ => {
+                    if accepts(source_x, source_y) {
+                        f(
+                            source_x,
+                            source_y,
+                            image.get_pixel(source_x, source_y).to_rgba(),
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgb, RgbImage};
+
+    use super::*;
+
+    #[test]
+    fn area_averages_accepted_pixels() {
+        let image = RgbImage::from_fn(1003, 1003, |x, _| {
+            if x < 501 {
+                Rgb([255, 0, 0])
+            } else {
+                Rgb([0, 0, 255])
+            }
+        });
+        let sampling = Sampling::default();
+        let mut count = 0;
+        for row in 0..sampling.rows(image.dimensions()) {
+            sampling.for_each_in_row(
+                &image,
+                row,
+                |x, _| x < 501,
+                |x, _, pixel| {
+                    assert!(x < 501);
+                    assert_eq!(pixel, Rgba([255, 0, 0, 255]));
+                    count += 1;
+                },
+            );
+        }
+        assert!(count > 0);
+    }
+
+    /// Positions and colors of the sampled pixels in the order they are visited
+    fn sampled(sampling: Sampling, image: &RgbImage) -> Vec<(u32, u32, Rgba<u8>)> {
+        let mut pixels = Vec::new();
+        for row in 0..sampling.rows(image.dimensions()) {
+            sampling.for_each_in_row(
+                image,
+                row,
+                |_, _| true,
+                |x, y, pixel| pixels.push((x, y, pixel)),
+            );
+        }
+        pixels
+    }
+
+    /// Image where every pixel has a different color
+    fn positions(width: u32, height: u32) -> RgbImage {
+        RgbImage::from_fn(width, height, |x, y| Rgb([x as u8, y as u8, 0]))
+    }
+
+    #[test]
+    fn stride_visits_every_nth_pixel_in_row_major_order() {
+        let image = positions(7, 5);
+        for step in [1, 2, 3, 8, 40] {
+            let expected = (0..35)
+                .step_by(step as usize)
+                .map(|i| (i % 7, i / 7, Rgba([(i % 7) as u8, (i / 7) as u8, 0, 255])))
+                .collect::<Vec<_>>();
+            assert_eq!(sampled(Sampling::Stride(step), &image), expected);
+        }
+        assert_eq!(
+            sampled(Sampling::Full, &image),
+            sampled(Sampling::Stride(1), &image)
+        );
+    }
+
+    #[test]
+    fn nearest_picks_pixels_under_grid() {
+        let image = positions(100, 50);
+        let pixels = sampled(Sampling::Nearest { max_pixels: 200 }, &image);
+        let expected = (0..10)
+            .flat_map(|y| (0..20).map(move |x| (5 * x + 2, 5 * y + 2)))
+            .map(|(x, y)| (x, y, Rgba([x as u8, y as u8, 0, 255])))
+            .collect::<Vec<_>>();
+        assert_eq!(pixels, expected);
+    }
+
+    #[test]
+    fn small_images_are_used_fully() {
+        let image = positions(10, 10);
+        let full = sampled(Sampling::Full, &image);
+        assert_eq!(full.len(), 100);
+        for max_pixels in [100, 1000] {
+            assert_eq!(sampled(Sampling::Area { max_pixels }, &image), full);
+            assert_eq!(sampled(Sampling::Nearest { max_pixels }, &image), full);
+        }
+    }
+
+    #[test]
+    fn thin_images_stay_within_budget() {
+        for (width, height) in [(1000, 1), (1, 1000), (2000, 3)] {
+            let image = positions(width, height);
+            for sampling in [
+                Sampling::Area { max_pixels: 100 },
+                Sampling::Nearest { max_pixels: 100 },
+            ] {
+                assert_eq!(sampled(sampling, &image).len(), 100, "{:?}", sampling);
+            }
+        }
+    }
+
+    #[test]
+    fn zero_budgets_are_invalid() {
+        for sampling in [
+            Sampling::Stride(0),
+            Sampling::Area { max_pixels: 0 },
+            Sampling::Nearest { max_pixels: 0 },
+        ] {
+            assert!(matches!(
+                sampling.validate(),
+                Err(Error::InvalidSampling(s)) if s == sampling
+            ));
+        }
+        assert!(Sampling::Stride(1).validate().is_ok());
+    }
+}
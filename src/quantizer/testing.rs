@@ -0,0 +1,23 @@
+//! Fixtures shared by the tests of the quantizers
+
+use image::{RgbImage, Rgba};
+
+use crate::{Color, PixelConfig, Quantizer, Sampling};
+
+/// Colors of every pixel of the image
+pub(crate) fn quantize<Q: Quantizer>(quantizer: &Q, image: &RgbImage, colors: usize) -> Vec<Color> {
+    let config = PixelConfig::default().with_sampling(Sampling::Full);
+    quantizer
+        .quantize(image, colors, &config, |_: &Rgba<u8>| true)
+        .unwrap()
+}
+
+/// Xorshift random number generator, so the images are the same on every platform
+pub(crate) fn xorshift(mut state: u32) -> impl FnMut() -> u32 {
+    move || {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        state
+    }
+}
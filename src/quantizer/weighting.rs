@@ -0,0 +1,178 @@
+use std::convert::TryFrom;
+
+use image::{GrayImage, Rgba};
+
+/// Weight of a pixel which counts fully
+pub(crate) const FULL_WEIGHT: u64 = u8::MAX as u64;
+/// Fraction of the full weight of the pixels in the corners with [`Weighting::Center`]
+const MIN_CENTER_WEIGHT: f64 = 0.25;
+
+/// Weighted population in pixels, rounded to the nearest pixel and saturating on 32-bit targets
+///
+/// Any weight counts as at least one pixel, so light clusters aren't mistaken for generated colors
+/// without population.
+pub(crate) fn population(weight: u64) -> usize {
+    let pixels = ((weight + FULL_WEIGHT / 2) / FULL_WEIGHT).max(u64::from(weight != 0));
+    usize::try_from(pixels).unwrap_or(usize::MAX)
+}
+
+/// Value of the map stretched to an image of the given dimensions at the position, 0 for an empty
+/// map
+pub(crate) fn stretched(map: &GrayImage, x: u32, y: u32, (width, height): (u32, u32)) -> u8 {
+    let (map_width, map_height) = map.dimensions();
+    if map_width == 0 || map_height == 0 {
+        return 0;
+    }
+    let scale =
+        |v: u32, side: u32, map_side: u32| (v as u64 * map_side as u64 / side as u64) as u32;
+    map.get_pixel(scale(x, width, map_width), scale(y, height, map_height))
+        .0[0]
+}
+
+/// How much each pixel contributes to the population of its color
+///
+/// Quantizers also weight the pixels when finding the colors, so heavier pixels pull the colors
+/// towards them. Pixels with a weight of zero are ignored.
+#[derive(Debug, Clone, Copy, Default)]
+pub enum Weighting<'a> {
+    /// Every pixel counts as one
+    #[default]
+    Uniform,
+    /// Pixels count by their opacity, so semi-transparent edges contribute less
+    Alpha,
+    /// Pixels count by the value of the map at their position, where white counts as one
+    ///
+    /// The map is stretched to the size of the image.
+    Map(&'a GrayImage),
+    /// Pixels count less the further they are from the center, down to a quarter in the corners
+    Center,
+    /// Pixels count by how much they attract attention, using the [`saliency_map`]
+    ///
+    /// The map is estimated for every quantization, pass it as a [`Weighting::Map`] to reuse it.
+    ///
+    /// [`saliency_map`]: crate::saliency_map
+    Saliency,
+}
+
+impl Weighting<'_> {
+    /// Weight of the pixel at the position in an image of the given dimensions, from 0 to
+    /// [`FULL_WEIGHT`]
+    pub(crate) fn weight(
+        &self,
+        x: u32,
+        y: u32,
+        (width, height): (u32, u32),
+        pixel: &Rgba<u8>,
+    ) -> u64 {
+        match self {
+            Weighting::Uniform => FULL_WEIGHT,
+            Weighting::Alpha => pixel.0[3] as u64,
+            Weighting::Map(map) => stretched(map, x, y, (width, height)) as u64,
+            // Replaced by its map before the pixels are weighted
+            Weighting::Saliency => FULL_WEIGHT,
+            Weighting::Center => {
+                let offset = |v: u32, side: u32| (v as f64 + 0.5_f64) / side as f64 - 0.5_f64;
+                // Squared distance from the center, 1 in the corners
+                let distance = 2_f64 * (offset(x, width).powi(2) + offset(y, height).powi(2));
+                let weight = 1_f64 - (1_f64 - MIN_CENTER_WEIGHT) * distance;
+                (weight * FULL_WEIGHT as f64).round() as u64
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{GenericImageView, Luma, Pixel, Rgb, RgbImage, Rgba, RgbaImage};
+
+    use super::*;
+    use crate::{Color, MedianCut, PixelConfig, Quantizer, Sampling, Vibrancy};
+
+    fn quantize<I, P>(image: &I, weighting: Weighting) -> Vec<Color>
+    where
+        P: Pixel<Subpixel = u8> + 'static,
+        I: GenericImageView<Pixel = P> + Sync,
+    {
+        let config = PixelConfig::default()
+            .with_sampling(Sampling::Full)
+            .with_weighting(weighting);
+        MedianCut::default()
+            .quantize(image, 8, &config, |_: &Rgba<u8>| true)
+            .unwrap()
+    }
+
+    #[test]
+    fn light_clusters_keep_a_population() {
+        assert_eq!(population(0), 0);
+        assert_eq!(population(1), 1);
+        assert_eq!(population(2 * FULL_WEIGHT - 1), 2);
+        assert_eq!(population(FULL_WEIGHT * 7), 7);
+    }
+
+    #[test]
+    fn low_weight_clusters_are_selectable() {
+        // Two faint red pixels weigh less than half a pixel
+        let image = RgbaImage::from_fn(16, 16, |x, y| {
+            if x < 2 && y == 0 {
+                Rgba([220, 40, 40, 60])
+            } else {
+                Rgba([128, 128, 128, 255])
+            }
+        });
+        let palette = quantize(&image, Weighting::Alpha);
+        let primary = Vibrancy::from_palette(&palette).primary.unwrap();
+        let [r, g, b] = primary.color.0;
+        assert!(r > 200 && g < 60 && b < 60, "{:?}", primary.color);
+        assert_eq!(primary.population, 1);
+    }
+
+    #[test]
+    fn faint_images_are_split() {
+        // The whole image weighs less than half a pixel
+        let image = RgbaImage::from_fn(10, 10, |x, y| {
+            Rgba([(x * 25) as u8, (y * 25) as u8, ((x + y) * 12) as u8, 1])
+        });
+        assert_eq!(quantize(&image, Weighting::Alpha).len(), 8);
+    }
+
+    #[test]
+    fn half_alpha_halves_population() {
+        let opaque = RgbaImage::from_pixel(20, 20, Rgba([40, 80, 160, 255]));
+        let half = RgbaImage::from_pixel(20, 20, Rgba([40, 80, 160, 128]));
+        assert_eq!(quantize(&opaque, Weighting::Alpha)[0].population, 400);
+        assert_eq!(quantize(&half, Weighting::Alpha)[0].population, 201);
+        assert_eq!(quantize(&half, Weighting::Uniform)[0].population, 400);
+    }
+
+    #[test]
+    fn center_counts_corners_a_quarter() {
+        let pixel = Rgba([0, 0, 0, 255]);
+        let weight = |x, y| Weighting::Center.weight(x, y, (101, 101), &pixel) as f64;
+        assert_eq!(weight(50, 50), FULL_WEIGHT as f64);
+        for (x, y) in [(0, 0), (100, 0), (0, 100), (100, 100)] {
+            let fraction = weight(x, y) / FULL_WEIGHT as f64;
+            assert!((0.25..0.27).contains(&fraction), "{}", fraction);
+        }
+        // Halfway to the edges a pixel counts more than in the corners
+        assert!(weight(25, 50) > weight(0, 50) && weight(0, 50) > weight(0, 0));
+    }
+
+    #[test]
+    fn map_is_stretched_over_image() {
+        let image = RgbImage::from_fn(40, 10, |x, _| {
+            if x < 20 {
+                Rgb([200, 40, 40])
+            } else {
+                Rgb([40, 40, 200])
+            }
+        });
+        let map = GrayImage::from_fn(4, 1, |x, _| Luma([[255, 255, 128, 0][x as usize]]));
+        let mut palette = quantize(&image, Weighting::Map(&map))
+            .iter()
+            .map(|color| color.population)
+            .collect::<Vec<_>>();
+        palette.sort_unstable();
+        // The blue half is half weighted and half ignored
+        assert_eq!(palette, [50, 200]);
+    }
+}
@@ -0,0 +1,461 @@
+use std::ops::Range;
+
+use image::{GenericImageView, Pixel, Rgb, Rgba};
+
+use super::weighting;
+use crate::{Color, Error, PixelConfig, Quantizer};
+
+/// Bits per channel of the histogram
+const BITS: usize = 5;
+/// Buckets per channel, index 0 is kept empty for the cumulative moments
+const SIDE: usize = (1 << BITS) + 1;
+
+fn index(r: usize, g: usize, b: usize) -> usize {
+    (r * SIDE + g) * SIDE + b
+}
+
+#[derive(Debug, Copy, Clone)]
+enum Dimension {
+    R,
+    G,
+    B,
+}
+
+/// Box in the histogram, lower bounds are exclusive and upper bounds inclusive
+#[derive(Debug, Copy, Clone, Default)]
+struct Cube {
+    r0: usize,
+    r1: usize,
+    g0: usize,
+    g1: usize,
+    b0: usize,
+    b1: usize,
+}
+
+impl Cube {
+    fn volume(&self) -> usize {
+        (self.r1 - self.r0) * (self.g1 - self.g0) * (self.b1 - self.b0)
+    }
+}
+
+/// Cumulative moments of the colors, so the moments of any box can be computed in constant time
+struct Moments {
+    weights: Vec<i64>,
+    r: Vec<i64>,
+    g: Vec<i64>,
+    b: Vec<i64>,
+    squares: Vec<f64>,
+}
+
+trait Moment {
+    type Value: Copy + std::ops::Add<Output = Self::Value> + std::ops::Sub<Output = Self::Value>;
+
+    fn at(&self, index: usize) -> Self::Value;
+
+    fn volume(&self, cube: &Cube) -> Self::Value {
+        let &Cube {
+            r0,
+            r1,
+            g0,
+            g1,
+            b0,
+            b1,
+        } = cube;
+        self.at(index(r1, g1, b1)) - self.at(index(r1, g1, b0)) - self.at(index(r1, g0, b1))
+            + self.at(index(r1, g0, b0))
+            - self.at(index(r0, g1, b1))
+            + self.at(index(r0, g1, b0))
+            + self.at(index(r0, g0, b1))
+            - self.at(index(r0, g0, b0))
+    }
+
+    /// Part of the volume of the cube that does not depend on the cut position along `dimension`
+    fn bottom(&self, cube: &Cube, dimension: Dimension) -> Self::Value {
+        let &Cube {
+            r0,
+            r1,
+            g0,
+            g1,
+            b0,
+            b1,
+        } = cube;
+        match dimension {
+            Dimension::R => {
+                self.at(index(r0, g1, b0)) + self.at(index(r0, g0, b1))
+                    - self.at(index(r0, g1, b1))
+                    - self.at(index(r0, g0, b0))
+            }
+            Dimension::G => {
+                self.at(index(r1, g0, b0)) + self.at(index(r0, g0, b1))
+                    - self.at(index(r1, g0, b1))
+                    - self.at(index(r0, g0, b0))
+            }
+            Dimension::B => {
+                self.at(index(r1, g0, b0)) + self.at(index(r0, g1, b0))
+                    - self.at(index(r1, g1, b0))
+                    - self.at(index(r0, g0, b0))
+            }
+        }
+    }
+
+    /// Part of the volume of the cube below `position` along `dimension`
+    fn top(&self, cube: &Cube, dimension: Dimension, position: usize) -> Self::Value {
+        let &Cube {
+            r0,
+            r1,
+            g0,
+            g1,
+            b0,
+            b1,
+        } = cube;
+        match dimension {
+            Dimension::R => {
+                self.at(index(position, g1, b1)) + self.at(index(position, g0, b0))
+                    - self.at(index(position, g1, b0))
+                    - self.at(index(position, g0, b1))
+            }
+            Dimension::G => {
+                self.at(index(r1, position, b1)) + self.at(index(r0, position, b0))
+                    - self.at(index(r1, position, b0))
+                    - self.at(index(r0, position, b1))
+            }
+            Dimension::B => {
+                self.at(index(r1, g1, position)) + self.at(index(r0, g0, position))
+                    - self.at(index(r1, g0, position))
+                    - self.at(index(r0, g1, position))
+            }
+        }
+    }
+}
+
+impl Moment for Vec<i64> {
+    type Value = i64;
+
+    fn at(&self, index: usize) -> i64 {
+        self[index]
+    }
+}
+
+impl Moment for Vec<f64> {
+    type Value = f64;
+
+    fn at(&self, index: usize) -> f64 {
+        self[index]
+    }
+}
+
+impl Moments {
+    fn new() -> Self {
+        Self {
+            weights: vec![0; SIDE * SIDE * SIDE],
+            r: vec![0; SIDE * SIDE * SIDE],
+            g: vec![0; SIDE * SIDE * SIDE],
+            b: vec![0; SIDE * SIDE * SIDE],
+            squares: vec![0_f64; SIDE * SIDE * SIDE],
+        }
+    }
+
+    fn insert(&mut self, pixel: &Rgba<u8>, weight: u64) {
+        let [r, g, b, _] = pixel.0;
+        let bucket = |c: u8| (c as usize >> (8 - BITS)) + 1;
+        let i = index(bucket(r), bucket(g), bucket(b));
+        let (r, g, b, weight) = (r as i64, g as i64, b as i64, weight as i64);
+        self.weights[i] += weight;
+        self.r[i] += r * weight;
+        self.g[i] += g * weight;
+        self.b[i] += b * weight;
+        self.squares[i] += ((r * r + g * g + b * b) * weight) as f64;
+    }
+
+    /// Turn the histogram into cumulative moments
+    fn accumulate(&mut self) {
+        for r in 1..SIDE {
+            let mut area = [0_i64; SIDE];
+            let mut area_r = [0_i64; SIDE];
+            let mut area_g = [0_i64; SIDE];
+            let mut area_b = [0_i64; SIDE];
+            let mut area_squares = [0_f64; SIDE];
+            for g in 1..SIDE {
+                let mut line = 0_i64;
+                let mut line_r = 0_i64;
+                let mut line_g = 0_i64;
+                let mut line_b = 0_i64;
+                let mut line_squares = 0_f64;
+                for b in 1..SIDE {
+                    let i = index(r, g, b);
+                    line += self.weights[i];
+                    line_r += self.r[i];
+                    line_g += self.g[i];
+                    line_b += self.b[i];
+                    line_squares += self.squares[i];
+
+                    area[b] += line;
+                    area_r[b] += line_r;
+                    area_g[b] += line_g;
+                    area_b[b] += line_b;
+                    area_squares[b] += line_squares;
+
+                    let previous = index(r - 1, g, b);
+                    self.weights[i] = self.weights[previous] + area[b];
+                    self.r[i] = self.r[previous] + area_r[b];
+                    self.g[i] = self.g[previous] + area_g[b];
+                    self.b[i] = self.b[previous] + area_b[b];
+                    self.squares[i] = self.squares[previous] + area_squares[b];
+                }
+            }
+        }
+    }
+
+    fn variance(&self, cube: &Cube) -> f64 {
+        if cube.volume() <= 1 {
+            return 0_f64;
+        }
+        let r = self.r.volume(cube) as f64;
+        let g = self.g.volume(cube) as f64;
+        let b = self.b.volume(cube) as f64;
+        let weight = self.weights.volume(cube) as f64;
+        if weight == 0_f64 {
+            return 0_f64;
+        }
+        self.squares.volume(cube) - (r * r + g * g + b * b) / weight
+    }
+
+    /// Best position to cut the cube along `dimension` and the resulting reduction of variance
+    fn maximize(
+        &self,
+        cube: &Cube,
+        dimension: Dimension,
+        range: Range<usize>,
+    ) -> (f64, Option<usize>) {
+        let whole_r = self.r.volume(cube);
+        let whole_g = self.g.volume(cube);
+        let whole_b = self.b.volume(cube);
+        let whole_weight = self.weights.volume(cube);
+
+        let bottom_r = self.r.bottom(cube, dimension);
+        let bottom_g = self.g.bottom(cube, dimension);
+        let bottom_b = self.b.bottom(cube, dimension);
+        let bottom_weight = self.weights.bottom(cube, dimension);
+
+        let score = |r: i64, g: i64, b: i64, weight: i64| {
+            let (r, g, b) = (r as f64, g as f64, b as f64);
+            (r * r + g * g + b * b) / weight as f64
+        };
+
+        let mut max = 0_f64;
+        let mut cut = None;
+        for position in range {
+            let half_r = bottom_r + self.r.top(cube, dimension, position);
+            let half_g = bottom_g + self.g.top(cube, dimension, position);
+            let half_b = bottom_b + self.b.top(cube, dimension, position);
+            let half_weight = bottom_weight + self.weights.top(cube, dimension, position);
+            let other_weight = whole_weight - half_weight;
+            if half_weight == 0 || other_weight == 0 {
+                continue;
+            }
+
+            let value = score(half_r, half_g, half_b, half_weight)
+                + score(
+                    whole_r - half_r,
+                    whole_g - half_g,
+                    whole_b - half_b,
+                    other_weight,
+                );
+            if value > max {
+                max = value;
+                cut = Some(position);
+            }
+        }
+        (max, cut)
+    }
+
+    /// Split `cube` along the dimension which reduces the variance most
+    fn cut(&self, cube: &mut Cube) -> Option<Cube> {
+        let (max_r, cut_r) = self.maximize(cube, Dimension::R, cube.r0 + 1..cube.r1);
+        let (max_g, cut_g) = self.maximize(cube, Dimension::G, cube.g0 + 1..cube.g1);
+        let (max_b, cut_b) = self.maximize(cube, Dimension::B, cube.b0 + 1..cube.b1);
+
+        let mut other = *cube;
+        if max_r >= max_g && max_r >= max_b {
+            let cut = cut_r?;
+            cube.r1 = cut;
+            other.r0 = cut;
+        } else if max_g >= max_r && max_g >= max_b {
+            let cut = cut_g?;
+            cube.g1 = cut;
+            other.g0 = cut;
+        } else {
+            let cut = cut_b?;
+            cube.b1 = cut;
+            other.b0 = cut;
+        }
+        Some(other)
+    }
+
+    fn average(&self, cube: &Cube) -> Option<Color> {
+        let weight = self.weights.volume(cube);
+        if weight <= 0 {
+            return None;
+        }
+        let mean = |sum: i64| (sum as f64 / weight as f64).round() as u8;
+        Some(Color {
+            color: Rgb([
+                mean(self.r.volume(cube)),
+                mean(self.g.volume(cube)),
+                mean(self.b.volume(cube)),
+            ]),
+            population: weighting::population(weight as u64),
+        })
+    }
+}
+
+/// Xiaolin Wu's variance minimizing quantizer
+///
+/// The colors are put into a histogram with 5 bits per channel, like the default
+/// [`MedianCut`](crate::MedianCut).
+/// The box with the largest variance is split repeatedly at the position which minimizes the sum
+/// of the variances of both halves.
+#[derive(Debug, Default)]
+pub struct Wu;
+
+const COLOR_RANGE: Range<usize> = 2..257;
+
+impl Quantizer for Wu {
+    fn quantize<I, P, F>(
+        &self,
+        image: &I,
+        colors: usize,
+        config: &PixelConfig,
+        filter: F,
+    ) -> Result<Vec<Color>, Error>
+    where
+        P: Pixel<Subpixel = u8> + 'static,
+        I: GenericImageView<Pixel = P> + Sync,
+        F: Fn(&Rgba<u8>) -> bool + Sync,
+    {
+        if !COLOR_RANGE.contains(&colors) {
+            return Err(Error::ColorCountOutOfBounds(colors, COLOR_RANGE));
+        }
+
+        let mut moments = Moments::new();
+        config
+            .pixels(image, filter)?
+            .for_each(|pixel, weight| moments.insert(&pixel, weight));
+        moments.accumulate();
+        if moments.weights[index(SIDE - 1, SIDE - 1, SIDE - 1)] == 0 {
+            return Err(Error::NoPixels);
+        }
+
+        let mut cubes = vec![Cube {
+            r1: SIDE - 1,
+            g1: SIDE - 1,
+            b1: SIDE - 1,
+            ..Cube::default()
+        }];
+        let mut variances = vec![moments.variance(&cubes[0])];
+        while cubes.len() < colors {
+            // Split the box with the largest variance
+            let (next, &variance) = variances
+                .iter()
+                .enumerate()
+                .max_by(|(_, a), (_, b)| a.total_cmp(b))
+                .unwrap();
+            if variance <= 0_f64 {
+                break;
+            }
+            match moments.cut(&mut cubes[next]) {
+                Some(other) => {
+                    variances[next] = moments.variance(&cubes[next]);
+                    variances.push(moments.variance(&other));
+                    cubes.push(other);
+                }
+                None => variances[next] = 0_f64,
+            }
+        }
+
+        Ok(cubes
+            .iter()
+            .filter_map(|cube| moments.average(cube))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgb, RgbImage};
+
+    use super::*;
+    use crate::quantizer::testing::{quantize, xorshift};
+    use crate::MedianCut;
+
+    /// Gradient, noise and flat blocks
+    fn images() -> Vec<RgbImage> {
+        let mut random = xorshift(0x2545_f491);
+        vec![
+            RgbImage::from_fn(64, 48, |x, y| Rgb([(x * 4) as u8, (y * 5) as u8, 128])),
+            RgbImage::from_fn(40, 40, |_, _| {
+                let [r, g, b, _] = random().to_le_bytes();
+                Rgb([r, g, b])
+            }),
+            RgbImage::from_fn(30, 20, |x, y| match (x / 10, y / 10) {
+                (0, 0) => Rgb([200, 30, 30]),
+                (1, 0) => Rgb([30, 200, 30]),
+                (2, 0) => Rgb([30, 30, 200]),
+                (0, _) => Rgb([240, 240, 240]),
+                (1, _) => Rgb([20, 20, 20]),
+                _ => Rgb([200, 200, 30]),
+            }),
+        ]
+    }
+
+    /// Sum of the squared distances of the pixels to their nearest color
+    fn squared_error(image: &RgbImage, colors: &[Color]) -> u64 {
+        image
+            .pixels()
+            .map(|pixel| {
+                colors
+                    .iter()
+                    .map(|color| {
+                        pixel
+                            .0
+                            .iter()
+                            .zip(color.color.0)
+                            .map(|(&a, b)| (a as i64 - b as i64).pow(2) as u64)
+                            .sum::<u64>()
+                    })
+                    .min()
+                    .unwrap()
+            })
+            .sum()
+    }
+
+    #[test]
+    fn colors_are_bounded() {
+        for image in images() {
+            for colors in [2, 5, 16, 64] {
+                assert!(quantize(&Wu, &image, colors).len() <= colors);
+            }
+        }
+    }
+
+    #[test]
+    fn populations_sum_to_pixels() {
+        for image in images() {
+            let colors = quantize(&Wu, &image, 16);
+            let population = colors.iter().map(|c| c.population).sum::<usize>();
+            assert_eq!(population, image.pixels().len());
+        }
+    }
+
+    #[test]
+    fn error_is_at_most_median_cut() {
+        for image in images() {
+            for colors in [4, 8, 16] {
+                let wu = squared_error(&image, &quantize(&Wu, &image, colors));
+                let median_cut =
+                    squared_error(&image, &quantize(&MedianCut::default(), &image, colors));
+                assert!(wu <= median_cut, "{} > {}", wu, median_cut);
+            }
+        }
+    }
+}
@@ -0,0 +1,139 @@
+use image::{imageops::thumbnail, GenericImageView, GrayImage, Luma, Pixel};
+
+use crate::oklab::Oklab;
+
+/// Longest side of the image the saliency is estimated on
+const SIZE: u32 = 64;
+/// Weight of the least salient pixels, so they still count a little
+const MIN_WEIGHT: f64 = 0.1;
+
+/// Blurs the rows of the colors with a binomial kernel, clamping at the edges
+fn blur_rows(colors: &[Oklab], width: usize) -> Vec<Oklab> {
+    const KERNEL: [f64; 5] = [1_f64, 4_f64, 6_f64, 4_f64, 1_f64];
+    let mut blurred = Vec::with_capacity(colors.len());
+    for row in colors.chunks_exact(width) {
+        for x in 0..width {
+            let mut sum = Oklab::default();
+            for (i, k) in KERNEL.iter().enumerate() {
+                let c = row[(x + i).saturating_sub(2).min(width - 1)];
+                sum.l += k * c.l;
+                sum.a += k * c.a;
+                sum.b += k * c.b;
+            }
+            blurred.push(Oklab {
+                l: sum.l / 16_f64,
+                a: sum.a / 16_f64,
+                b: sum.b / 16_f64,
+            });
+        }
+    }
+    blurred
+}
+
+/// Swaps rows and columns
+fn transpose(colors: &[Oklab], width: usize) -> Vec<Oklab> {
+    let height = colors.len() / width;
+    (0..width)
+        .flat_map(|x| (0..height).map(move |y| colors[y * width + x]))
+        .collect()
+}
+
+/// Estimates which parts of the image attract attention, like the frequency-tuned saliency of
+/// Achanta et al.
+///
+/// A slightly blurred copy of the image is compared to its mean color in OKLab, so regions which
+/// stand out from the rest are salient. The map is at most 64 pixels wide and high and can be used
+/// with [`Weighting::Map`](crate::Weighting::Map). Even the least salient pixels keep a tenth of
+/// the weight, uniform images are fully salient.
+pub fn saliency_map<I, P>(image: &I) -> GrayImage
+where
+    P: Pixel<Subpixel = u8> + 'static,
+    I: GenericImageView<Pixel = P>,
+{
+    let (width, height) = image.dimensions();
+    if width == 0 || height == 0 {
+        return GrayImage::new(width, height);
+    }
+    let scale = (SIZE as f64 / width.max(height) as f64).min(1_f64);
+    let scaled = |side: u32| ((side as f64 * scale).round() as u32).max(1);
+    let small = thumbnail(image, scaled(width), scaled(height));
+    let (width, height) = small.dimensions();
+
+    let colors = small
+        .pixels()
+        .map(|pixel| Oklab::from_rgb(&pixel.to_rgb()))
+        .collect::<Vec<_>>();
+    let count = colors.len() as f64;
+    let mean = colors.iter().fold(Oklab::default(), |mean, c| Oklab {
+        l: mean.l + c.l / count,
+        a: mean.a + c.a / count,
+        b: mean.b + c.b / count,
+    });
+    let blurred = blur_rows(&colors, width as usize);
+    let blurred = blur_rows(&transpose(&blurred, width as usize), height as usize);
+    let blurred = transpose(&blurred, height as usize);
+
+    let saliency = blurred
+        .iter()
+        .map(|c| c.distance(&mean))
+        .collect::<Vec<_>>();
+    let max = saliency.iter().copied().fold(0_f64, f64::max);
+    let min = MIN_WEIGHT;
+    GrayImage::from_fn(width, height, |x, y| {
+        let s = saliency[(y * width + x) as usize];
+        let s = if max > 0_f64 { s / max } else { 1_f64 };
+        Luma([((min + (1_f64 - min) * s) * u8::MAX as f64).round() as u8])
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgb, RgbImage};
+
+    use super::*;
+
+    /// Red square in the middle of a gray image
+    fn square(width: u32, height: u32) -> RgbImage {
+        RgbImage::from_fn(width, height, |x, y| {
+            let inside = |v: u32, side: u32| (side * 3 / 8..side * 5 / 8).contains(&v);
+            if inside(x, width) && inside(y, height) {
+                Rgb([220, 30, 30])
+            } else {
+                Rgb([120, 120, 120])
+            }
+        })
+    }
+
+    #[test]
+    fn distinct_square_is_salient() {
+        let map = saliency_map(&square(64, 64));
+        let center = map.get_pixel(32, 32).0[0];
+        assert_eq!(center, u8::MAX);
+        for (x, y) in [(0, 0), (63, 0), (0, 63), (63, 63)] {
+            assert!(map.get_pixel(x, y).0[0] < center / 2);
+        }
+    }
+
+    #[test]
+    fn uniform_image_is_fully_salient() {
+        let map = saliency_map(&RgbImage::from_pixel(30, 20, Rgb([10, 150, 90])));
+        assert_eq!(map.dimensions(), (30, 20));
+        assert!(map.pixels().all(|pixel| pixel.0[0] == u8::MAX));
+    }
+
+    #[test]
+    fn map_is_at_most_64_pixels() {
+        assert_eq!(saliency_map(&square(640, 320)).dimensions(), (64, 32));
+        assert_eq!(saliency_map(&square(100, 1000)).dimensions(), (6, 64));
+        assert_eq!(saliency_map(&square(48, 40)).dimensions(), (48, 40));
+        assert_eq!(saliency_map(&RgbImage::new(0, 5)).dimensions(), (0, 5));
+    }
+
+    #[test]
+    fn map_keeps_min_weight() {
+        let min = (MIN_WEIGHT * u8::MAX as f64).round() as u8;
+        let map = saliency_map(&square(200, 150));
+        assert!(map.pixels().all(|pixel| pixel.0[0] >= min));
+        assert_eq!(map.pixels().map(|pixel| pixel.0[0]).min(), Some(min));
+    }
+}
@@ -1,5 +1,3 @@
-#![allow(dead_code)]
-
 pub const TARGET_DARK_LUMA: f64 = 0.26;
 pub const MAX_DARK_LUMA: f64 = 0.45;
 
@@ -19,3 +17,26 @@ pub const MIN_VIBRANT_SATURATION: f64 = 0.35;
 pub const WEIGHT_SATURATION: f64 = 3.0;
 pub const WEIGHT_LUMA: f64 = 6.0;
 pub const WEIGHT_POPULATION: f64 = 1.0;
+pub const WEIGHT_HUE: f64 = 3.0;
+
+pub const MIN_CONTRAST_TITLE_TEXT: f64 = 3.0;
+pub const MIN_CONTRAST_BODY_TEXT: f64 = 4.5;
+
+pub const OKLCH_MIN_CHROMA: f64 = 1e-4;
+pub const OKLCH_MAX_CHROMA: f64 = 0.32;
+
+pub const OKLCH_TARGET_DARK_LUMA: f64 = 0.4;
+pub const OKLCH_MAX_DARK_LUMA: f64 = 0.52;
+
+pub const OKLCH_MIN_LIGHT_LUMA: f64 = 0.72;
+pub const OKLCH_TARGET_LIGHT_LUMA: f64 = 0.85;
+
+pub const OKLCH_MIN_NORMAL_LUMA: f64 = 0.5;
+pub const OKLCH_TARGET_NORMAL_LUMA: f64 = 0.63;
+pub const OKLCH_MAX_NORMAL_LUMA: f64 = 0.76;
+
+pub const OKLCH_TARGET_MUTED_SATURATION: f64 = 0.15;
+pub const OKLCH_MAX_MUTED_SATURATION: f64 = 0.3;
+
+pub const OKLCH_TARGET_VIBRANT_SATURATION: f64 = 0.8;
+pub const OKLCH_MIN_VIBRANT_SATURATION: f64 = 0.3;
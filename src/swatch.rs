@@ -0,0 +1,139 @@
+use image::{Rgb, Rgba};
+
+use crate::{contrast, settings, Color};
+
+const WHITE: Rgb<u8> = Rgb([u8::MAX, u8::MAX, u8::MAX]);
+const BLACK: Rgb<u8> = Rgb([0, 0, 0]);
+
+/// Color together with text colors that are readable on top of it
+///
+/// Like the swatches of the Android palette library the text colors are white or black with the
+/// smallest alpha that still reaches the minimum contrast ratio for titles and body text.
+#[derive(Debug, Hash, PartialEq, Eq, Clone, Copy)]
+pub struct Swatch {
+    /// Background color
+    pub color: Color,
+    /// Color of titles on the background
+    pub title_text_color: Rgba<u8>,
+    /// Color of body text on the background
+    pub body_text_color: Rgba<u8>,
+}
+
+impl Swatch {
+    /// Create a new swatch, computing the text colors for the given background
+    pub fn new(color: Color) -> Self {
+        let background = &color.color;
+        let text = |foreground: &Rgb<u8>, min_contrast| {
+            contrast::min_alpha(foreground, background, min_contrast)
+                .map(|alpha| with_alpha(foreground, alpha))
+        };
+        let light_title = text(&WHITE, settings::MIN_CONTRAST_TITLE_TEXT);
+        let light_body = text(&WHITE, settings::MIN_CONTRAST_BODY_TEXT);
+        let dark_title = text(&BLACK, settings::MIN_CONTRAST_TITLE_TEXT);
+        let dark_body = text(&BLACK, settings::MIN_CONTRAST_BODY_TEXT);
+
+        // Prefer a matching pair of white or black text, and mix them only if neither works
+        let (title_text_color, body_text_color) = match (light_title, light_body) {
+            (Some(title), Some(body)) => (title, body),
+            _ => match (dark_title, dark_body) {
+                (Some(title), Some(body)) => (title, body),
+                _ => (
+                    light_title
+                        .or(dark_title)
+                        .unwrap_or(with_alpha(&BLACK, u8::MAX)),
+                    light_body
+                        .or(dark_body)
+                        .unwrap_or(with_alpha(&BLACK, u8::MAX)),
+                ),
+            },
+        };
+
+        Self {
+            color,
+            title_text_color,
+            body_text_color,
+        }
+    }
+}
+
+impl From<Color> for Swatch {
+    fn from(color: Color) -> Self {
+        Self::new(color)
+    }
+}
+
+fn with_alpha(color: &Rgb<u8>, alpha: u8) -> Rgba<u8> {
+    let [r, g, b] = color.0;
+    Rgba([r, g, b, alpha])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn swatch(color: Rgb<u8>) -> Swatch {
+        Swatch::new(Color {
+            color,
+            population: 1,
+        })
+    }
+
+    #[test]
+    fn white_background_has_translucent_black_text() {
+        let swatch = swatch(WHITE);
+        for text in [swatch.title_text_color, swatch.body_text_color] {
+            assert_eq!(&text.0[..3], &BLACK.0);
+            assert!(text.0[3] < u8::MAX);
+        }
+    }
+
+    #[test]
+    fn black_background_has_white_text() {
+        let swatch = swatch(BLACK);
+        for text in [swatch.title_text_color, swatch.body_text_color] {
+            assert_eq!(&text.0[..3], &WHITE.0);
+        }
+    }
+
+    #[test]
+    fn text_reaches_min_contrast() {
+        let grays = (0..=u8::MAX).step_by(5).map(|v| Rgb([v, v, v]));
+        let colors = [
+            Rgb([220, 40, 40]),
+            Rgb([40, 160, 60]),
+            Rgb([30, 60, 200]),
+            Rgb([250, 220, 40]),
+            Rgb([120, 90, 180]),
+        ];
+        for background in grays.chain(colors) {
+            let swatch = swatch(background);
+            let contrast = |text: &Rgba<u8>| {
+                contrast::contrast_ratio(&contrast::composite(text, &background), &background)
+            };
+            assert!(contrast(&swatch.title_text_color) >= settings::MIN_CONTRAST_TITLE_TEXT);
+            assert!(contrast(&swatch.body_text_color) >= settings::MIN_CONTRAST_BODY_TEXT);
+        }
+    }
+
+    #[test]
+    fn min_alpha_is_smallest() {
+        for (foreground, background) in
+            [(BLACK, WHITE), (WHITE, BLACK), (WHITE, Rgb([30, 60, 200]))]
+        {
+            for min_contrast in [3_f64, 4.5] {
+                let alpha = contrast::min_alpha(&foreground, &background, min_contrast).unwrap();
+                let contrast = |alpha| {
+                    let text = with_alpha(&foreground, alpha);
+                    contrast::contrast_ratio(&contrast::composite(&text, &background), &background)
+                };
+                assert!(contrast(alpha) >= min_contrast);
+                assert!(contrast(alpha - 1) < min_contrast);
+            }
+        }
+        // Even opaque white has too little contrast on light gray
+        assert_eq!(
+            contrast::min_alpha(&WHITE, &Rgb([200, 200, 200]), 3_f64),
+            None
+        );
+    }
+}
@@ -0,0 +1,575 @@
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+use hsl::HSL;
+use image::{Pixel, Rgb};
+
+use crate::oklab::Oklab;
+use crate::{settings, Color};
+
+/// Minimum, Maximum, Target
+#[derive(Debug, Hash, Clone, Copy, PartialEq)]
+pub struct MinMaxTarget<T> {
+    /// Smallest accepted value
+    pub min: T,
+    /// Preferred value
+    pub target: T,
+    /// Largest accepted value
+    pub max: T,
+}
+
+impl MinMaxTarget<f64> {
+    pub(crate) fn contains(&self, value: f64) -> bool {
+        self.min <= value && value <= self.max
+    }
+
+    fn contains_hue(&self, hue: f64) -> bool {
+        if self.min <= self.max {
+            self.contains(hue)
+        } else {
+            // The range wraps around 360°
+            self.min <= hue || hue <= self.max
+        }
+    }
+
+    fn to_bits(self) -> [u64; 3] {
+        [self.min, self.target, self.max].map(f64::to_bits)
+    }
+}
+
+/// Color space used to rate colors for targets
+#[derive(Debug, Hash, PartialEq, Eq, Clone, Copy, Default)]
+pub enum ColorSpace {
+    /// Luma, saturation and hue are the lightness, saturation and hue of HSL
+    #[default]
+    Hsl,
+    /// Luma, saturation and hue are the lightness, relative chroma and hue of OKLCH
+    ///
+    /// The relative chroma is the chroma divided by the largest chroma of sRGB colors, so it
+    /// ranges from 0 to 1 like the HSL saturation. Unlike HSL this matches the perceived
+    /// lightness and colorfulness across hues.
+    Oklch,
+}
+
+/// Position of a color in a [`ColorSpace`]
+#[derive(Clone, Copy)]
+pub(crate) struct Coordinates {
+    pub luma: f64,
+    pub saturation: f64,
+    pub hue: f64,
+}
+
+impl ColorSpace {
+    pub(crate) fn coordinates(self, color: &Rgb<u8>) -> Coordinates {
+        match self {
+            ColorSpace::Hsl => {
+                let HSL { h, s, l } = HSL::from_rgb(color.channels());
+                Coordinates {
+                    luma: l,
+                    saturation: s,
+                    hue: h,
+                }
+            }
+            ColorSpace::Oklch => {
+                let Oklab { l, a, b } = Oklab::from_rgb(color);
+                let chroma = (a * a + b * b).sqrt();
+                // Grays only have a chroma due to rounding errors
+                let saturation = if chroma < settings::OKLCH_MIN_CHROMA {
+                    0_f64
+                } else {
+                    (chroma / settings::OKLCH_MAX_CHROMA).min(1_f64)
+                };
+                Coordinates {
+                    luma: l,
+                    saturation,
+                    hue: b.atan2(a).to_degrees().rem_euclid(360_f64),
+                }
+            }
+        }
+    }
+
+    /// Coordinates moved into the sRGB gamut by reducing the saturation, keeping the luma and hue
+    ///
+    /// Every HSL color is an sRGB color. Clipping the channels of OKLCH colors instead would shift
+    /// their hue and lightness.
+    pub(crate) fn to_gamut(self, coordinates: &Coordinates) -> Coordinates {
+        const ITERATIONS: usize = 24;
+
+        let oklab = |saturation: f64| {
+            let chroma = saturation * settings::OKLCH_MAX_CHROMA;
+            let hue = coordinates.hue.to_radians();
+            Oklab {
+                l: coordinates.luma,
+                a: chroma * hue.cos(),
+                b: chroma * hue.sin(),
+            }
+        };
+        if self == ColorSpace::Hsl || oklab(coordinates.saturation).in_gamut() {
+            return *coordinates;
+        }
+        // Binary search for the largest saturation in the gamut
+        let (mut inside, mut outside) = (0_f64, coordinates.saturation);
+        for _ in 0..ITERATIONS {
+            let saturation = (inside + outside) / 2_f64;
+            if oklab(saturation).in_gamut() {
+                inside = saturation;
+            } else {
+                outside = saturation;
+            }
+        }
+        Coordinates {
+            saturation: inside,
+            ..*coordinates
+        }
+    }
+
+    /// Nearest sRGB color of the coordinates, see [`ColorSpace::to_gamut`]
+    pub(crate) fn to_rgb(self, coordinates: &Coordinates) -> Rgb<u8> {
+        let Coordinates {
+            luma,
+            saturation,
+            hue,
+        } = self.to_gamut(coordinates);
+        match self {
+            ColorSpace::Hsl => {
+                let (r, g, b) = HSL {
+                    h: hue,
+                    s: saturation,
+                    l: luma,
+                }
+                .to_rgb();
+                Rgb([r, g, b])
+            }
+            ColorSpace::Oklch => {
+                let chroma = saturation * settings::OKLCH_MAX_CHROMA;
+                let hue = hue.to_radians();
+                Oklab {
+                    l: luma,
+                    a: chroma * hue.cos(),
+                    b: chroma * hue.sin(),
+                }
+                .to_rgb()
+            }
+        }
+    }
+}
+
+/// Weights of the individual scores when rating a color for a target
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Weights {
+    /// Weight of the saturation distance
+    pub saturation: f64,
+    /// Weight of the luma distance
+    pub luma: f64,
+    /// Weight of the population
+    pub population: f64,
+    /// Weight of the hue distance, only used by targets with a hue
+    pub hue: f64,
+}
+
+impl Default for Weights {
+    fn default() -> Self {
+        Self {
+            saturation: settings::WEIGHT_SATURATION,
+            luma: settings::WEIGHT_LUMA,
+            population: settings::WEIGHT_POPULATION,
+            hue: settings::WEIGHT_HUE,
+        }
+    }
+}
+
+/// Description of a color to pick from a palette
+///
+/// Modeled after `Palette.Target` of the Android palette library. Only colors within the luma,
+/// saturation and optionally hue ranges are considered, the one closest to the targets wins. The
+/// meaning of the values depends on the [`ColorSpace`] of the selector.
+///
+/// Targets are equal if all their values have the same bits, so they can be used as keys.
+#[derive(Debug, Clone, Copy)]
+pub struct Target {
+    /// Accepted and preferred luma
+    pub luma: MinMaxTarget<f64>,
+    /// Accepted and preferred saturation
+    pub saturation: MinMaxTarget<f64>,
+    /// Accepted and preferred hue in degrees
+    ///
+    /// A range with `min > max` wraps around, e.g. `330..30` for reds. Grays have no hue and are
+    /// never accepted when this is set.
+    pub hue: Option<MinMaxTarget<f64>>,
+    /// Weights of the scores
+    pub weights: Weights,
+    /// Whether the chosen color is unavailable to the following targets
+    pub exclusive: bool,
+}
+
+const fn preset(luma: MinMaxTarget<f64>, saturation: MinMaxTarget<f64>) -> Target {
+    Target {
+        luma,
+        saturation,
+        hue: None,
+        weights: Weights {
+            saturation: settings::WEIGHT_SATURATION,
+            luma: settings::WEIGHT_LUMA,
+            population: settings::WEIGHT_POPULATION,
+            hue: settings::WEIGHT_HUE,
+        },
+        exclusive: true,
+    }
+}
+
+pub(crate) const NORMAL_LUMA: MinMaxTarget<f64> = MinMaxTarget {
+    min: settings::MIN_NORMAL_LUMA,
+    target: settings::TARGET_NORMAL_LUMA,
+    max: settings::MAX_NORMAL_LUMA,
+};
+
+pub(crate) const DARK_LUMA: MinMaxTarget<f64> = MinMaxTarget {
+    min: 0_f64,
+    target: settings::TARGET_DARK_LUMA,
+    max: settings::MAX_DARK_LUMA,
+};
+
+pub(crate) const LIGHT_LUMA: MinMaxTarget<f64> = MinMaxTarget {
+    min: settings::MIN_LIGHT_LUMA,
+    target: settings::TARGET_LIGHT_LUMA,
+    max: 1_f64,
+};
+
+pub(crate) const VIBRANT_SATURATION: MinMaxTarget<f64> = MinMaxTarget {
+    min: settings::MIN_VIBRANT_SATURATION,
+    target: settings::TARGET_VIBRANT_SATURATION,
+    max: 1_f64,
+};
+
+pub(crate) const MUTED_SATURATION: MinMaxTarget<f64> = MinMaxTarget {
+    min: 0_f64,
+    target: settings::TARGET_MUTED_SATURATION,
+    max: settings::MAX_MUTED_SATURATION,
+};
+
+impl Target {
+    /// Primary vibrant color
+    pub const VIBRANT: Target = preset(NORMAL_LUMA, VIBRANT_SATURATION);
+    /// Light vibrant color
+    pub const LIGHT_VIBRANT: Target = preset(LIGHT_LUMA, VIBRANT_SATURATION);
+    /// Dark vibrant color
+    pub const DARK_VIBRANT: Target = preset(DARK_LUMA, VIBRANT_SATURATION);
+    /// Muted color
+    pub const MUTED: Target = preset(NORMAL_LUMA, MUTED_SATURATION);
+    /// Light muted color
+    pub const LIGHT_MUTED: Target = preset(LIGHT_LUMA, MUTED_SATURATION);
+    /// Dark muted color
+    pub const DARK_MUTED: Target = preset(DARK_LUMA, MUTED_SATURATION);
+
+    /// Restrict this target to the given hue range
+    pub fn with_hue(self, hue: MinMaxTarget<f64>) -> Self {
+        Self {
+            hue: Some(hue),
+            ..self
+        }
+    }
+
+    fn accepts(&self, coordinates: &Coordinates) -> bool {
+        self.saturation.contains(coordinates.saturation)
+            && self.luma.contains(coordinates.luma)
+            && self.hue.map_or(true, |hue| {
+                coordinates.saturation > 0_f64 && hue.contains_hue(coordinates.hue)
+            })
+    }
+
+    fn score(&self, coordinates: &Coordinates, population: f64, max_population: f64) -> Score {
+        let saturation = invert_diff(coordinates.saturation, self.saturation.target);
+        let luma = invert_diff(coordinates.luma, self.luma.target);
+        let population = population / max_population;
+        let hue = self
+            .hue
+            .map(|hue| 1_f64 - hue_distance(coordinates.hue, hue.target) / 180_f64);
+        let total = weighted_mean(&[
+            (saturation, self.weights.saturation),
+            (luma, self.weights.luma),
+            (population, self.weights.population),
+            hue.map_or((0_f64, 0_f64), |hue| (hue, self.weights.hue)),
+        ]);
+        Score {
+            total,
+            saturation,
+            luma,
+            population,
+            hue,
+        }
+    }
+}
+
+/// Bits of the values of a [`Target`], used for equality and hashing
+#[derive(PartialEq, Eq, Hash)]
+struct TargetBits {
+    luma: [u64; 3],
+    saturation: [u64; 3],
+    hue: Option<[u64; 3]>,
+    weights: [u64; 4],
+    exclusive: bool,
+}
+
+impl Target {
+    fn to_bits(self) -> TargetBits {
+        let Weights {
+            saturation,
+            luma,
+            population,
+            hue,
+        } = self.weights;
+        TargetBits {
+            luma: self.luma.to_bits(),
+            saturation: self.saturation.to_bits(),
+            hue: self.hue.map(MinMaxTarget::to_bits),
+            weights: [saturation, luma, population, hue].map(f64::to_bits),
+            exclusive: self.exclusive,
+        }
+    }
+}
+
+impl PartialEq for Target {
+    fn eq(&self, other: &Self) -> bool {
+        self.to_bits() == other.to_bits()
+    }
+}
+
+impl Eq for Target {}
+
+impl Hash for Target {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.to_bits().hash(state);
+    }
+}
+
+/// Picks the best matching colors of a palette for a list of targets
+///
+/// Targets are resolved in order, so earlier exclusive targets take precedence.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Selector {
+    /// Targets to resolve
+    pub targets: Vec<Target>,
+    /// Minimum OKLab distance of a color to the colors chosen by earlier exclusive targets
+    ///
+    /// With 0 only the exact same colors are rejected. Distances below about 0.02 are barely
+    /// noticeable, 0.1 keeps the colors clearly apart.
+    pub min_distance: f64,
+    /// Color space the targets are defined in
+    pub space: ColorSpace,
+}
+
+impl Selector {
+    /// Create a new selector for the given targets
+    pub fn new(targets: Vec<Target>) -> Self {
+        Self {
+            targets,
+            min_distance: 0_f64,
+            space: ColorSpace::Hsl,
+        }
+    }
+
+    /// Set the minimum distance between chosen colors
+    pub fn with_min_distance(self, min_distance: f64) -> Self {
+        Self {
+            min_distance,
+            ..self
+        }
+    }
+
+    /// Set the color space the targets are defined in
+    pub fn with_space(self, space: ColorSpace) -> Self {
+        Self { space, ..self }
+    }
+
+    /// Choose a color for each target, targets without a matching color are missing in the map
+    pub fn select(&self, palette: &[Color]) -> HashMap<Target, Color> {
+        self.targets
+            .iter()
+            .zip(self.select_in_order(palette))
+            .filter_map(|(target, color)| color.map(|color| (*target, color)))
+            .collect()
+    }
+
+    /// Rank the eligible colors for each target, in the order of the targets
+    ///
+    /// The candidates are sorted by descending score. Colors chosen by earlier exclusive targets
+    /// are not eligible, so the first candidate is the color [`Selector::select`] picks.
+    pub fn rank(&self, palette: &[Color]) -> Vec<Vec<Candidate>> {
+        let max_population = palette.iter().map(|c| c.population).max().unwrap_or(0);
+        let mut used = Vec::<(Rgb<u8>, Oklab)>::new();
+        self.targets
+            .iter()
+            .map(|target| {
+                let is_used = |color: &Rgb<u8>| {
+                    let lab = Oklab::from_rgb(color);
+                    used.iter().any(|(used, used_lab)| {
+                        used == color || used_lab.distance(&lab) < self.min_distance
+                    })
+                };
+                let candidates = rank_colors(palette, target, self.space, is_used, max_population);
+                if let Some(candidate) = candidates.first().filter(|_| target.exclusive) {
+                    let color = candidate.color.color;
+                    used.push((color, Oklab::from_rgb(&color)));
+                }
+                candidates
+            })
+            .collect()
+    }
+
+    /// Choose a color for each target, in the order of the targets
+    pub(crate) fn select_in_order(&self, palette: &[Color]) -> Vec<Option<Color>> {
+        self.rank(palette)
+            .into_iter()
+            .map(|candidates| candidates.first().map(|candidate| candidate.color))
+            .collect()
+    }
+}
+
+/// Rating of a color for a target
+///
+/// All individual scores range from 0 to 1, higher is better.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Score {
+    /// Weighted mean of the individual scores
+    pub total: f64,
+    /// Closeness to the target saturation
+    pub saturation: f64,
+    /// Closeness to the target luma
+    pub luma: f64,
+    /// Population relative to the most common color of the palette
+    pub population: f64,
+    /// Closeness to the target hue, if the target has one
+    pub hue: Option<f64>,
+}
+
+/// Color eligible for a target
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Candidate {
+    /// Color of the palette
+    pub color: Color,
+    /// Score of the color for the target
+    pub score: Score,
+}
+
+fn rank_colors(
+    palette: &[Color],
+    target: &Target,
+    space: ColorSpace,
+    is_used: impl Fn(&Rgb<u8>) -> bool,
+    max_population: usize,
+) -> Vec<Candidate> {
+    let mut candidates = palette
+        .iter()
+        .filter(|color| color.population != 0 && !is_used(&color.color))
+        .filter_map(|&color| {
+            let coordinates = space.coordinates(&color.color);
+            target.accepts(&coordinates).then(|| Candidate {
+                color,
+                score: target.score(&coordinates, color.population as f64, max_population as f64),
+            })
+        })
+        .collect::<Vec<_>>();
+    // Stable, so the first of equally good colors stays in front
+    candidates.sort_by(|a, b| b.score.total.total_cmp(&a.score.total));
+    candidates
+}
+
+fn invert_diff(val: f64, target_val: f64) -> f64 {
+    1_f64 - (val - target_val).abs()
+}
+
+fn hue_distance(a: f64, b: f64) -> f64 {
+    let diff = (a - b).rem_euclid(360_f64);
+    diff.min(360_f64 - diff)
+}
+
+fn weighted_mean(values: &[(f64, f64)]) -> f64 {
+    let (sum, sum_weight) = values
+        .iter()
+        .fold((0_f64, 0_f64), |(sum, sum_weight), &(val, weight)| {
+            (sum + val * weight, sum_weight + weight)
+        });
+
+    sum / sum_weight
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::color;
+
+    /// Any luma and saturation, hues from 330° to 30°
+    fn reds() -> Target {
+        let any = MinMaxTarget {
+            min: 0_f64,
+            target: 0.5,
+            max: 1_f64,
+        };
+        Target {
+            luma: any,
+            saturation: any,
+            ..Target::VIBRANT
+        }
+        .with_hue(MinMaxTarget {
+            min: 330_f64,
+            target: 0_f64,
+            max: 30_f64,
+        })
+    }
+
+    #[test]
+    fn hue_distance_wraps_around() {
+        assert_eq!(hue_distance(350_f64, 10_f64), 20_f64);
+        assert_eq!(hue_distance(10_f64, 350_f64), 20_f64);
+        assert_eq!(hue_distance(0_f64, 180_f64), 180_f64);
+        assert_eq!(hue_distance(300_f64, 0_f64), 60_f64);
+    }
+
+    #[test]
+    fn wrapping_hue_range_picks_red() {
+        let red = color(0xFF002B, 10);
+        let magenta = color(0xFF00FF, 100);
+        let orange = color(0xFFAA00, 100);
+        let palette = [magenta, red, orange, color(0x808080, 100)];
+        let selector = Selector::new(vec![reds()]);
+
+        let ranked = selector.rank(&palette);
+        assert_eq!(ranked[0].len(), 1);
+        assert_eq!(ranked[0][0].color, red);
+        assert_eq!(selector.select(&palette).get(&reds()), Some(&red));
+    }
+
+    #[test]
+    fn hue_range_rejects_grays() {
+        let palette = [
+            color(0x000000, 10),
+            color(0x808080, 10),
+            color(0xFFFFFF, 10),
+        ];
+        for space in [ColorSpace::Hsl, ColorSpace::Oklch] {
+            let selector = Selector::new(vec![reds()]).with_space(space);
+            assert!(selector.select(&palette).is_empty());
+        }
+    }
+
+    #[test]
+    fn min_distance_separates_similar_colors() {
+        let red = color(0xDC2828, 100);
+        let similar = color(0xD72A28, 90);
+        let blue = color(0x283CC8, 10);
+        let targets = vec![Target::VIBRANT, Target::VIBRANT];
+
+        let selector = Selector::new(targets.clone());
+        assert_eq!(
+            selector.select_in_order(&[red, similar, blue]),
+            [Some(red), Some(similar)]
+        );
+
+        let selector = Selector::new(targets).with_min_distance(0.1);
+        assert_eq!(
+            selector.select_in_order(&[red, similar, blue]),
+            [Some(red), Some(blue)]
+        );
+        assert_eq!(selector.select_in_order(&[red, similar]), [Some(red), None]);
+    }
+}
@@ -0,0 +1,14 @@
+//! Fixtures shared by the tests
+
+use image::Rgb;
+
+use crate::Color;
+
+/// Color of a hex code like `0xRRGGBB` with a population
+pub(crate) fn color(hex: u32, population: usize) -> Color {
+    let [_, r, g, b] = hex.to_be_bytes();
+    Color {
+        color: Rgb([r, g, b]),
+        population,
+    }
+}
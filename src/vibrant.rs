@@ -1,10 +1,7 @@
-use std::array::IntoIter;
 use std::fmt;
 
-use hsl::HSL;
-use image::{Pixel, Rgb};
-
-use crate::{settings, Color};
+use crate::target::{self, Coordinates};
+use crate::{settings, Candidate, Color, ColorSpace, MinMaxTarget, Selector, Target, Weights};
 
 /// Vibrancy
 ///
@@ -27,150 +24,130 @@ pub struct Vibrancy {
 
 impl Vibrancy {
     /// Create new vibrancy map from an image
+    ///
+    /// All colors are `None` if the palette is empty.
     pub fn from_palette(palette: &[Color]) -> Vibrancy {
-        let mut vibrancy = Vibrancy::default();
-        let max_population = palette.iter().map(|c| c.population).max().unwrap();
-        vibrancy.primary = vibrancy.find_color_variation(
-            palette,
-            &MinMaxTarget {
-                min: settings::MIN_NORMAL_LUMA,
-                target: settings::TARGET_NORMAL_LUMA,
-                max: settings::MAX_NORMAL_LUMA,
-            },
-            &MinMaxTarget {
-                min: settings::MIN_VIBRANT_SATURATION,
-                target: settings::TARGET_VIBRANT_SATURATION,
-                max: 1_f64,
-            },
-            max_population,
-        );
+        Self::from_palette_with(palette, &VibrancyConfig::default())
+    }
 
-        vibrancy.light = vibrancy.find_color_variation(
-            palette,
-            &MinMaxTarget {
-                min: settings::MIN_LIGHT_LUMA,
-                target: settings::TARGET_LIGHT_LUMA,
-                max: 1_f64,
-            },
-            &MinMaxTarget {
-                min: settings::MIN_VIBRANT_SATURATION,
-                target: settings::TARGET_VIBRANT_SATURATION,
-                max: 1_f64,
-            },
-            max_population,
-        );
+    /// Create new vibrancy map from an image using the given targets and weights
+    pub fn from_palette_with(palette: &[Color], config: &VibrancyConfig) -> Vibrancy {
+        let mut colors = config.selector().select_in_order(palette).into_iter();
+        let mut next = || colors.next().unwrap();
+        let mut vibrancy = Vibrancy {
+            primary: next(),
+            light: next(),
+            dark: next(),
+            muted: next(),
+            light_muted: next(),
+            dark_muted: next(),
+        };
+        if config.generate_missing {
+            vibrancy.generate_missing(config);
+        }
+        vibrancy
+    }
 
-        vibrancy.dark = vibrancy.find_color_variation(
-            palette,
-            &MinMaxTarget {
-                min: 0_f64,
-                target: settings::TARGET_DARK_LUMA,
-                max: settings::MAX_DARK_LUMA,
-            },
-            &MinMaxTarget {
-                min: settings::MIN_VIBRANT_SATURATION,
-                target: settings::TARGET_VIBRANT_SATURATION,
-                max: 1_f64,
-            },
-            max_population,
-        );
+    /// Derive missing colors from the found ones by moving them to the targets of the slot
+    fn generate_missing(&mut self, config: &VibrancyConfig) {
+        let vibrant_source = [self.primary, self.dark, self.light]
+            .iter()
+            .flatten()
+            .next()
+            .copied();
+        let muted_source = [
+            self.muted,
+            self.dark_muted,
+            self.light_muted,
+            self.primary,
+            self.dark,
+            self.light,
+        ]
+        .iter()
+        .flatten()
+        .next()
+        .copied();
 
-        vibrancy.muted = vibrancy.find_color_variation(
-            palette,
-            &MinMaxTarget {
-                min: settings::MIN_NORMAL_LUMA,
-                target: settings::TARGET_NORMAL_LUMA,
-                max: settings::MAX_NORMAL_LUMA,
-            },
-            &MinMaxTarget {
-                min: 0_f64,
-                target: settings::TARGET_MUTED_SATURATION,
-                max: settings::MAX_MUTED_SATURATION,
-            },
-            max_population,
+        let fill = |slot: &mut Option<Color>,
+                    source: Option<Color>,
+                    luma: &MinMaxTarget<f64>,
+                    saturation: &MinMaxTarget<f64>| {
+            if slot.is_none() {
+                *slot = source.and_then(|source| generate(&source, config.space, luma, saturation));
+            }
+        };
+        fill(
+            &mut self.primary,
+            vibrant_source,
+            &config.normal_luma,
+            &config.vibrant_saturation,
         );
-
-        vibrancy.light_muted = vibrancy.find_color_variation(
-            palette,
-            &MinMaxTarget {
-                min: settings::MIN_LIGHT_LUMA,
-                target: settings::TARGET_LIGHT_LUMA,
-                max: 1_f64,
-            },
-            &MinMaxTarget {
-                min: 0_f64,
-                target: settings::TARGET_MUTED_SATURATION,
-                max: settings::MAX_MUTED_SATURATION,
-            },
-            max_population,
+        fill(
+            &mut self.light,
+            vibrant_source,
+            &config.light_luma,
+            &config.vibrant_saturation,
         );
-
-        vibrancy.dark_muted = vibrancy.find_color_variation(
-            palette,
-            &MinMaxTarget {
-                min: 0_f64,
-                target: settings::TARGET_DARK_LUMA,
-                max: settings::MAX_DARK_LUMA,
-            },
-            &MinMaxTarget {
-                min: 0_f64,
-                target: settings::TARGET_MUTED_SATURATION,
-                max: settings::MAX_MUTED_SATURATION,
-            },
-            max_population,
+        fill(
+            &mut self.dark,
+            vibrant_source,
+            &config.dark_luma,
+            &config.vibrant_saturation,
+        );
+        fill(
+            &mut self.muted,
+            muted_source,
+            &config.normal_luma,
+            &config.muted_saturation,
+        );
+        fill(
+            &mut self.light_muted,
+            muted_source,
+            &config.light_luma,
+            &config.muted_saturation,
+        );
+        fill(
+            &mut self.dark_muted,
+            muted_source,
+            &config.dark_luma,
+            &config.muted_saturation,
         );
-
-        vibrancy
     }
+}
 
-    fn color_already_set(&self, color: &Rgb<u8>) -> bool {
-        IntoIter::new([
-            self.primary.as_ref(),
-            self.dark.as_ref(),
-            self.light.as_ref(),
-            self.muted.as_ref(),
-            self.dark_muted.as_ref(),
-            self.light_muted.as_ref(),
-        ])
-        .any(|v| v.map(|c| &c.color) == Some(color))
-    }
-
-    fn find_color_variation(
-        &self,
-        palette: &[Color],
-        luma: &MinMaxTarget<f64>,
-        saturation: &MinMaxTarget<f64>,
-        max_population: usize,
-    ) -> Option<Color> {
-        let mut max = None;
-        let mut max_value = 0_f64;
-
-        for &Color { color, population } in palette.iter() {
-            let HSL { h: _, s, l } = HSL::from_rgb(color.channels());
-
-            if population != 0
-                && s >= saturation.min
-                && s <= saturation.max
-                && l >= luma.min
-                && l <= luma.max
-                && !self.color_already_set(&color)
-            {
-                let value = create_comparison_value(
-                    s,
-                    saturation.target,
-                    l,
-                    luma.target,
-                    population as f64,
-                    max_population as f64,
-                );
-                if max.is_none() || value > max_value {
-                    max = Some(Color { color, population });
-                    max_value = value;
-                }
-            }
-        }
+/// Ranked candidates for each color of [`Vibrancy`]
+///
+/// The candidates are sorted by descending score, the first one is the color [`Vibrancy`] picks
+/// unless it was generated.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct VibrancyCandidates {
+    /// Candidates for the primary vibrant color
+    pub primary: Vec<Candidate>,
+    /// Candidates for the dark vibrant color
+    pub dark: Vec<Candidate>,
+    /// Candidates for the light vibrant color
+    pub light: Vec<Candidate>,
+    /// Candidates for the muted color
+    pub muted: Vec<Candidate>,
+    /// Candidates for the dark muted color
+    pub dark_muted: Vec<Candidate>,
+    /// Candidates for the light muted color
+    pub light_muted: Vec<Candidate>,
+}
 
-        max
+impl VibrancyCandidates {
+    /// Rank the colors of the palette for each vibrant color
+    pub fn from_palette_with(palette: &[Color], config: &VibrancyConfig) -> Self {
+        let mut candidates = config.selector().rank(palette).into_iter();
+        let mut next = || candidates.next().unwrap();
+        VibrancyCandidates {
+            primary: next(),
+            light: next(),
+            dark: next(),
+            muted: next(),
+            light_muted: next(),
+            dark_muted: next(),
+        }
     }
 }
 
@@ -202,39 +179,353 @@ impl fmt::Display for Vibrancy {
     }
 }
 
-fn invert_diff(val: f64, target_val: f64) -> f64 {
-    1_f64 - (val - target_val).abs()
+/// Generated color with the hue of `source` and the target luma of the slot
+///
+/// The saturation is kept if it is within the range of the slot. Grays have no hue to keep, so
+/// none is generated for slots which need a saturation. Colors outside of the sRGB gamut lose
+/// saturation instead of hue; if the slot's saturation can't be reached at the target luma, the
+/// closest luma of the slot which reaches it is used, and `None` if there is none. Generated
+/// colors have a population of 0.
+fn generate(
+    source: &Color,
+    space: ColorSpace,
+    luma: &MinMaxTarget<f64>,
+    saturation: &MinMaxTarget<f64>,
+) -> Option<Color> {
+    const LUMA_STEPS: u32 = 100;
+
+    let source = space.coordinates(&source.color);
+    let wanted = if saturation.contains(source.saturation) {
+        source.saturation
+    } else if source.saturation == 0_f64 {
+        return None;
+    } else {
+        saturation.target
+    };
+    // Lumas of the slot by their distance to the target
+    (0..=LUMA_STEPS)
+        .map(|step| step as f64 / LUMA_STEPS as f64)
+        .flat_map(|distance| [luma.target - distance, luma.target + distance])
+        .filter(|&l| luma.contains(l))
+        .map(|l| {
+            space.to_gamut(&Coordinates {
+                luma: l,
+                saturation: wanted,
+                hue: source.hue,
+            })
+        })
+        .find(|coordinates| saturation.contains(coordinates.saturation))
+        .map(|coordinates| Color {
+            color: space.to_rgb(&coordinates),
+            population: 0,
+        })
 }
 
-fn weighted_mean(values: &[(f64, f64)]) -> f64 {
-    let (sum, sum_weight) = values
-        .iter()
-        .fold((0_f64, 0_f64), |(sum, sum_weight), &(val, weight)| {
-            (sum + val * weight, sum_weight + weight)
-        });
+/// Targets and weights used to pick the vibrant colors
+///
+/// The default matches the values used by vibrant.js, [`VibrancyConfig::oklch`] provides targets
+/// in the OKLCH color space.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VibrancyConfig {
+    /// Color space the luma and saturation targets are defined in
+    pub space: ColorSpace,
+    /// Luma of the primary and muted colors
+    pub normal_luma: MinMaxTarget<f64>,
+    /// Luma of the dark colors
+    pub dark_luma: MinMaxTarget<f64>,
+    /// Luma of the light colors
+    pub light_luma: MinMaxTarget<f64>,
+    /// Saturation of the vibrant colors
+    pub vibrant_saturation: MinMaxTarget<f64>,
+    /// Saturation of the muted colors
+    pub muted_saturation: MinMaxTarget<f64>,
+    /// Weight of the saturation distance when scoring a color
+    pub weight_saturation: f64,
+    /// Weight of the luma distance when scoring a color
+    pub weight_luma: f64,
+    /// Weight of the population when scoring a color
+    pub weight_population: f64,
+    /// Minimum OKLab distance between the chosen colors, see [`Selector::min_distance`]
+    pub min_distance: f64,
+    /// Derive colors for slots without a matching color from the found colors
+    ///
+    /// Vibrant colors are only derived from vibrant colors and grays never gain a hue. Generated
+    /// colors have a population of 0. If no color is found at all, all slots stay empty.
+    pub generate_missing: bool,
+}
 
-    sum / sum_weight
+impl Default for VibrancyConfig {
+    fn default() -> Self {
+        Self {
+            space: ColorSpace::Hsl,
+            normal_luma: target::NORMAL_LUMA,
+            dark_luma: target::DARK_LUMA,
+            light_luma: target::LIGHT_LUMA,
+            vibrant_saturation: target::VIBRANT_SATURATION,
+            muted_saturation: target::MUTED_SATURATION,
+            weight_saturation: settings::WEIGHT_SATURATION,
+            weight_luma: settings::WEIGHT_LUMA,
+            weight_population: settings::WEIGHT_POPULATION,
+            min_distance: 0_f64,
+            generate_missing: false,
+        }
+    }
 }
 
-fn create_comparison_value(
-    sat: f64,
-    target_sat: f64,
-    luma: f64,
-    target_uma: f64,
-    population: f64,
-    max_population: f64,
-) -> f64 {
-    weighted_mean(&[
-        (invert_diff(sat, target_sat), settings::WEIGHT_SATURATION),
-        (invert_diff(luma, target_uma), settings::WEIGHT_LUMA),
-        (population / max_population, settings::WEIGHT_POPULATION),
-    ])
+impl VibrancyConfig {
+    /// Targets defined in the perceptual OKLCH color space
+    ///
+    /// Scoring by the perceived lightness and chroma avoids preferring yellows, which have a high
+    /// HSL saturation and lightness, over equally vibrant blues.
+    pub fn oklch() -> Self {
+        Self {
+            space: ColorSpace::Oklch,
+            normal_luma: MinMaxTarget {
+                min: settings::OKLCH_MIN_NORMAL_LUMA,
+                target: settings::OKLCH_TARGET_NORMAL_LUMA,
+                max: settings::OKLCH_MAX_NORMAL_LUMA,
+            },
+            dark_luma: MinMaxTarget {
+                min: 0_f64,
+                target: settings::OKLCH_TARGET_DARK_LUMA,
+                max: settings::OKLCH_MAX_DARK_LUMA,
+            },
+            light_luma: MinMaxTarget {
+                min: settings::OKLCH_MIN_LIGHT_LUMA,
+                target: settings::OKLCH_TARGET_LIGHT_LUMA,
+                max: 1_f64,
+            },
+            vibrant_saturation: MinMaxTarget {
+                min: settings::OKLCH_MIN_VIBRANT_SATURATION,
+                target: settings::OKLCH_TARGET_VIBRANT_SATURATION,
+                max: 1_f64,
+            },
+            muted_saturation: MinMaxTarget {
+                min: 0_f64,
+                target: settings::OKLCH_TARGET_MUTED_SATURATION,
+                max: settings::OKLCH_MAX_MUTED_SATURATION,
+            },
+            ..Self::default()
+        }
+    }
+
+    /// Selector resolving the targets in the order primary, light, dark, muted, light muted and
+    /// dark muted
+    pub fn selector(&self) -> Selector {
+        let weights = Weights {
+            saturation: self.weight_saturation,
+            luma: self.weight_luma,
+            population: self.weight_population,
+            hue: settings::WEIGHT_HUE,
+        };
+        let target = |luma, saturation| Target {
+            luma,
+            saturation,
+            hue: None,
+            weights,
+            exclusive: true,
+        };
+        Selector::new(vec![
+            target(self.normal_luma, self.vibrant_saturation),
+            target(self.light_luma, self.vibrant_saturation),
+            target(self.dark_luma, self.vibrant_saturation),
+            target(self.normal_luma, self.muted_saturation),
+            target(self.light_luma, self.muted_saturation),
+            target(self.dark_luma, self.muted_saturation),
+        ])
+        .with_min_distance(self.min_distance)
+        .with_space(self.space)
+    }
 }
 
-/// Minimum, Maximum, Target
-#[derive(Debug, Hash)]
-struct MinMaxTarget<T> {
-    min: T,
-    target: T,
-    max: T,
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::color;
+
+    fn generating(config: VibrancyConfig) -> VibrancyConfig {
+        VibrancyConfig {
+            generate_missing: true,
+            ..config
+        }
+    }
+
+    fn hue_distance(a: f64, b: f64) -> f64 {
+        let diff = (a - b).rem_euclid(360_f64);
+        diff.min(360_f64 - diff)
+    }
+
+    #[test]
+    fn config_weights_change_selection() {
+        // Pure red is on the targets, the lighter red is far more common
+        let red = color(0xFF0000, 1);
+        let common = color(0xEB4747, 100);
+        let palette = [red, common];
+        assert_eq!(Vibrancy::from_palette(&palette).primary, Some(red));
+        let config = VibrancyConfig {
+            weight_population: 10_f64,
+            ..VibrancyConfig::default()
+        };
+        assert_eq!(
+            Vibrancy::from_palette_with(&palette, &config).primary,
+            Some(common)
+        );
+    }
+
+    #[test]
+    fn missing_slots_are_generated_without_population() {
+        let red = color(0xC81E1E, 100);
+        let vibrancy = Vibrancy::from_palette_with(&[red], &generating(VibrancyConfig::default()));
+        assert_eq!(vibrancy.primary, Some(red));
+        for slot in [
+            vibrancy.light,
+            vibrancy.dark,
+            vibrancy.muted,
+            vibrancy.light_muted,
+            vibrancy.dark_muted,
+        ] {
+            assert_eq!(slot.unwrap().population, 0);
+        }
+        assert_eq!(Vibrancy::from_palette(&[red]).light, None);
+
+        // Both are out of gamut at the targets of the light vibrant color in OKLCH
+        let config = generating(VibrancyConfig::oklch());
+        for source in [0xAB41D7, 0xC81E1E] {
+            let vibrancy = Vibrancy::from_palette_with(&[color(source, 100)], &config);
+            assert_eq!(vibrancy.light.unwrap().population, 0);
+        }
+    }
+
+    #[test]
+    fn grays_generate_no_vibrant_colors() {
+        for config in [VibrancyConfig::default(), VibrancyConfig::oklch()] {
+            let vibrancy =
+                Vibrancy::from_palette_with(&[color(0x808080, 100)], &generating(config));
+            assert_eq!(vibrancy.primary, None);
+            assert_eq!(vibrancy.light, None);
+            assert_eq!(vibrancy.dark, None);
+            assert!(vibrancy.muted.is_some());
+        }
+    }
+
+    #[test]
+    fn generated_colors_keep_hue_within_slot() {
+        let sources = [
+            0xAB41D7, 0xC81E1E, 0xE0A020, 0x30B040, 0x2050D0, 0x20A0B0, 0xD04080,
+        ];
+        for config in [VibrancyConfig::default(), VibrancyConfig::oklch()] {
+            let config = generating(config);
+            for source in sources {
+                let source = color(source, 100);
+                let vibrancy = Vibrancy::from_palette_with(&[source], &config);
+                let source = config.space.coordinates(&source.color);
+                let slots = [
+                    (
+                        vibrancy.primary,
+                        config.normal_luma,
+                        config.vibrant_saturation,
+                    ),
+                    (vibrancy.light, config.light_luma, config.vibrant_saturation),
+                    (vibrancy.dark, config.dark_luma, config.vibrant_saturation),
+                    (vibrancy.muted, config.normal_luma, config.muted_saturation),
+                    (
+                        vibrancy.light_muted,
+                        config.light_luma,
+                        config.muted_saturation,
+                    ),
+                    (
+                        vibrancy.dark_muted,
+                        config.dark_luma,
+                        config.muted_saturation,
+                    ),
+                ];
+                for (slot, luma, saturation) in slots {
+                    // Teals can't be dark and vibrant in OKLCH, for example
+                    let generated = match slot {
+                        Some(color) if color.population == 0 => color,
+                        _ => continue,
+                    };
+                    let coordinates = config.space.coordinates(&generated.color);
+                    let message = format!("{:?} {} from {:?}", config.space, generated, source.hue);
+                    assert!(
+                        hue_distance(coordinates.hue, source.hue) < 4_f64,
+                        "{} has hue {}",
+                        message,
+                        coordinates.hue
+                    );
+                    assert!(luma.contains(coordinates.luma), "{}", message);
+                    assert!(saturation.contains(coordinates.saturation), "{}", message);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn oklch_prefers_blue_over_yellow() {
+        // The yellow is fully saturated in HSL, but much less colorful than the blue
+        let yellow = color(0xBEA000, 100);
+        let blue = color(0x285AE6, 100);
+        let ranked = |config: &VibrancyConfig| {
+            VibrancyCandidates::from_palette_with(&[yellow, blue], config)
+                .primary
+                .iter()
+                .map(|candidate| candidate.color)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ranked(&VibrancyConfig::default()), [yellow, blue]);
+        assert_eq!(ranked(&VibrancyConfig::oklch()), [blue, yellow]);
+    }
+
+    #[test]
+    fn candidates_are_ranked_without_used_colors() {
+        // The pink is the only primary color, but also a light one
+        let palette = [
+            color(0xF06E6E, 80),
+            color(0x78AAF0, 50),
+            color(0x8CE08C, 45),
+            color(0x142878, 40),
+            color(0x96786E, 30),
+            color(0xC8B4AA, 20),
+            color(0x463C37, 10),
+        ];
+        let config = VibrancyConfig::default();
+        let candidates = VibrancyCandidates::from_palette_with(&palette, &config);
+        let vibrancy = Vibrancy::from_palette_with(&palette, &config);
+        let slots = [
+            (&candidates.primary, vibrancy.primary),
+            (&candidates.light, vibrancy.light),
+            (&candidates.dark, vibrancy.dark),
+            (&candidates.muted, vibrancy.muted),
+            (&candidates.light_muted, vibrancy.light_muted),
+            (&candidates.dark_muted, vibrancy.dark_muted),
+        ];
+
+        // The same targets without excluding the colors of earlier targets
+        let mut selector = config.selector();
+        for target in &mut selector.targets {
+            target.exclusive = false;
+        }
+        let unrestricted = selector.rank(&palette);
+
+        let mut used = Vec::new();
+        let mut excluded = 0;
+        let mut ranked = 0;
+        for (&(candidates, picked), unrestricted) in slots.iter().zip(unrestricted) {
+            assert!(candidates
+                .windows(2)
+                .all(|pair| pair[0].score.total >= pair[1].score.total));
+            assert_eq!(candidates.first().map(|c| c.color), picked);
+            ranked += candidates.len().saturating_sub(1);
+
+            let count = unrestricted.len();
+            let available = unrestricted
+                .into_iter()
+                .filter(|c| !used.contains(&c.color))
+                .collect::<Vec<_>>();
+            excluded += count - available.len();
+            assert_eq!(candidates, &available);
+            used.extend(picked);
+        }
+        assert!(excluded > 0 && ranked > 0);
+    }
 }